@@ -0,0 +1,267 @@
+//! Classic BPF programs and their interpreter.
+
+/// Instruction classes.
+pub const BPF_LD: u16 = 0x00;
+pub const BPF_LDX: u16 = 0x01;
+pub const BPF_ST: u16 = 0x02;
+pub const BPF_STX: u16 = 0x03;
+pub const BPF_ALU: u16 = 0x04;
+pub const BPF_JMP: u16 = 0x05;
+pub const BPF_RET: u16 = 0x06;
+pub const BPF_MISC: u16 = 0x07;
+
+/// Load sizes.
+pub const BPF_W: u16 = 0x00;
+pub const BPF_H: u16 = 0x08;
+pub const BPF_B: u16 = 0x10;
+
+/// Load modes.
+pub const BPF_IMM: u16 = 0x00;
+pub const BPF_ABS: u16 = 0x20;
+pub const BPF_IND: u16 = 0x40;
+pub const BPF_MEM: u16 = 0x60;
+pub const BPF_LEN: u16 = 0x80;
+pub const BPF_MSH: u16 = 0xa0;
+
+/// ALU operations.
+pub const BPF_ADD: u16 = 0x00;
+pub const BPF_SUB: u16 = 0x10;
+pub const BPF_MUL: u16 = 0x20;
+pub const BPF_DIV: u16 = 0x30;
+pub const BPF_OR: u16 = 0x40;
+pub const BPF_AND: u16 = 0x50;
+pub const BPF_LSH: u16 = 0x60;
+pub const BPF_RSH: u16 = 0x70;
+pub const BPF_NEG: u16 = 0x80;
+pub const BPF_MOD: u16 = 0x90;
+pub const BPF_XOR: u16 = 0xa0;
+
+/// Jump conditions.
+pub const BPF_JA: u16 = 0x00;
+pub const BPF_JEQ: u16 = 0x10;
+pub const BPF_JGT: u16 = 0x20;
+pub const BPF_JGE: u16 = 0x30;
+pub const BPF_JSET: u16 = 0x40;
+
+/// Operand sources.
+pub const BPF_K: u16 = 0x00;
+pub const BPF_X: u16 = 0x08;
+pub const BPF_A: u16 = 0x10;
+
+/// Register transfers.
+pub const BPF_TAX: u16 = 0x00;
+pub const BPF_TXA: u16 = 0x80;
+
+/// Number of words of the scratch memory.
+const BPF_MEMWORDS: usize = 16;
+
+/// Classic BPF instruction (same layout as `struct sock_filter`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct Instruction {
+    /// Operation code.
+    pub code: u16,
+    /// Offset of the next instruction if the condition is true.
+    pub jt: u8,
+    /// Offset of the next instruction if the condition is false.
+    pub jf: u8,
+    /// Generic operand.
+    pub k: u32,
+}
+
+impl Instruction {
+    /// Construct a statement.
+    pub fn stmt(code: u16, k: u32) -> Instruction {
+        Instruction {
+            code,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    /// Construct a conditional jump.
+    pub fn jump(code: u16, k: u32, jt: u8, jf: u8) -> Instruction {
+        Instruction { code, jt, jf, k }
+    }
+}
+
+/// Read `size` bytes (big endian) at the given offset of the packet.
+fn load(packet: &[u8], offset: u32, size: u16) -> Option<u32> {
+    let offset = offset as usize;
+    let len = match size {
+        BPF_W => 4,
+        BPF_H => 2,
+        BPF_B => 1,
+        _ => return None,
+    };
+
+    let bytes = packet.get(offset..offset.checked_add(len)?)?;
+    Some(
+        bytes
+            .iter()
+            .fold(0, |value, b| (value << 8) | u32::from(*b)),
+    )
+}
+
+/// Run a program against a packet, whose original length is `wire_len`.
+/// Return the number of bytes of the packet to accept (0 to reject it).
+/// Invalid programs and out of bounds loads reject the packet.
+pub fn run(program: &[Instruction], packet: &[u8], wire_len: u32) -> u32 {
+    let mut a: u32 = 0;
+    let mut x: u32 = 0;
+    let mut mem = [0u32; BPF_MEMWORDS];
+    let mut pc = 0;
+
+    while let Some(insn) = program.get(pc) {
+        pc += 1;
+        let k = insn.k;
+
+        match insn.code & 0x07 {
+            BPF_LD => {
+                a = match insn.code & 0xe0 {
+                    BPF_IMM => k,
+                    BPF_ABS => match load(packet, k, insn.code & 0x18) {
+                        Some(value) => value,
+                        None => return 0,
+                    },
+                    BPF_IND => match x
+                        .checked_add(k)
+                        .and_then(|offset| load(packet, offset, insn.code & 0x18))
+                    {
+                        Some(value) => value,
+                        None => return 0,
+                    },
+                    BPF_MEM => match mem.get(k as usize) {
+                        Some(value) => *value,
+                        None => return 0,
+                    },
+                    BPF_LEN => wire_len,
+                    _ => return 0,
+                }
+            }
+            BPF_LDX => {
+                x = match insn.code & 0xe0 {
+                    BPF_IMM => k,
+                    BPF_MEM => match mem.get(k as usize) {
+                        Some(value) => *value,
+                        None => return 0,
+                    },
+                    BPF_LEN => wire_len,
+                    BPF_MSH => match load(packet, k, BPF_B) {
+                        Some(value) => (value & 0x0f) * 4,
+                        None => return 0,
+                    },
+                    _ => return 0,
+                }
+            }
+            BPF_ST | BPF_STX => {
+                let value = if insn.code & 0x07 == BPF_ST { a } else { x };
+                match mem.get_mut(k as usize) {
+                    Some(word) => *word = value,
+                    None => return 0,
+                }
+            }
+            BPF_ALU => {
+                let operand = if insn.code & BPF_X != 0 { x } else { k };
+                a = match insn.code & 0xf0 {
+                    BPF_ADD => a.wrapping_add(operand),
+                    BPF_SUB => a.wrapping_sub(operand),
+                    BPF_MUL => a.wrapping_mul(operand),
+                    BPF_DIV if operand != 0 => a / operand,
+                    BPF_MOD if operand != 0 => a % operand,
+                    BPF_OR => a | operand,
+                    BPF_AND => a & operand,
+                    BPF_LSH => a.checked_shl(operand).unwrap_or(0),
+                    BPF_RSH => a.checked_shr(operand).unwrap_or(0),
+                    BPF_NEG => a.wrapping_neg(),
+                    BPF_XOR => a ^ operand,
+                    _ => return 0,
+                }
+            }
+            BPF_JMP => {
+                let operand = if insn.code & BPF_X != 0 { x } else { k };
+                let taken = match insn.code & 0xf0 {
+                    BPF_JA => {
+                        pc = pc.saturating_add(k as usize);
+                        continue;
+                    }
+                    BPF_JEQ => a == operand,
+                    BPF_JGT => a > operand,
+                    BPF_JGE => a >= operand,
+                    BPF_JSET => a & operand != 0,
+                    _ => return 0,
+                };
+                pc += usize::from(if taken { insn.jt } else { insn.jf });
+            }
+            BPF_RET => {
+                return match insn.code & 0x18 {
+                    BPF_K => k,
+                    BPF_X => x,
+                    BPF_A => a,
+                    _ => 0,
+                }
+            }
+            BPF_MISC => match insn.code & 0xf8 {
+                BPF_TAX => x = a,
+                BPF_TXA => a = x,
+                _ => return 0,
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    // The program ended without returning.
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bpf::*;
+
+    #[test]
+    fn test_run() {
+        // Accept IPv4 packets whose IP header is followed by a byte greater than 0x10.
+        let program = [
+            Instruction::stmt(BPF_LD | BPF_H | BPF_ABS, 12),
+            Instruction::jump(BPF_JMP | BPF_JEQ | BPF_K, 0x0800, 0, 5),
+            Instruction::stmt(BPF_LDX | BPF_B | BPF_MSH, 14),
+            Instruction::stmt(BPF_LD | BPF_B | BPF_IND, 14),
+            Instruction::jump(BPF_JMP | BPF_JGT | BPF_K, 0x10, 0, 2),
+            Instruction::stmt(BPF_LD | BPF_W | BPF_LEN, 0),
+            Instruction::stmt(BPF_RET | BPF_A, 0),
+            Instruction::stmt(BPF_RET | BPF_K, 0),
+        ];
+
+        let mut packet = vec![0; 12];
+        packet.extend_from_slice(&[0x08, 0x00, 0x45]);
+        packet.resize(34, 0);
+        packet.push(0x11);
+
+        assert_eq!(run(&program, &packet, 100), 100);
+        packet[34] = 0x10;
+        assert_eq!(run(&program, &packet, 100), 0);
+        // Out of bounds load.
+        assert_eq!(run(&program, &packet[..34], 100), 0);
+        // Not IPv4.
+        packet[12] = 0x86;
+        assert_eq!(run(&program, &packet, 100), 0);
+
+        // Scratch memory, ALU and register transfers.
+        let program = [
+            Instruction::stmt(BPF_LD | BPF_IMM, 6),
+            Instruction::stmt(BPF_ST, 3),
+            Instruction::stmt(BPF_LDX | BPF_MEM, 3),
+            Instruction::stmt(BPF_ALU | BPF_MUL | BPF_X, 0),
+            Instruction::stmt(BPF_ALU | BPF_DIV | BPF_K, 4),
+            Instruction::stmt(BPF_MISC | BPF_TAX, 0),
+            Instruction::stmt(BPF_MISC | BPF_TXA, 0),
+            Instruction::stmt(BPF_RET | BPF_A, 0),
+        ];
+        assert_eq!(run(&program, &[], 0), 9);
+
+        // Division by zero and missing return.
+        let program = [Instruction::stmt(BPF_ALU | BPF_DIV | BPF_K, 0)];
+        assert_eq!(run(&program, &[], 0), 0);
+    }
+}
@@ -0,0 +1,830 @@
+//! Compile filter expressions in the pcap-filter syntax (e.g. "tcp port 443 and host 10.0.0.5")
+//! to classic BPF programs, evaluated against Ethernet frames.
+//!
+//! Supported primitives:
+//! - `ether`, `ip`, `ip6`, `arp`, `tcp`, `udp`, `sctp`, `icmp`, `icmp6`
+//! - `[ether|ip|ip6|arp] [src|dst] host ADDR`
+//! - `[ip|ip6] [src|dst] net ADDR/LEN`
+//! - `[tcp|udp|sctp] [src|dst] port PORT` and `portrange PORT-PORT`
+//! - `[ether|ip|ip6] proto PROTO`
+//! - `vlan [ID]` (the following primitives refer to the encapsulated frame)
+//! - `less LEN` and `greater LEN`
+//!
+//! combined with `and` (`&&`), `or` (`||`), `not` (`!`) and parentheses.
+
+use std::net::IpAddr;
+
+use crate::{
+    bpf::{self, *},
+    link::{self, ETHERTYPE_ARP, ETHERTYPE_IPV4, ETHERTYPE_IPV6, ETHERTYPE_QINQ, ETHERTYPE_VLAN},
+};
+
+/// Number of bytes of the matching packets returned by the programs.
+const SNAPLEN: u32 = 262144;
+
+/// Length of the Ethernet header.
+const ETHERNET_HEADER_LEN: u32 = 14;
+/// Length of the IPv6 header.
+const IPV6_HEADER_LEN: u32 = 40;
+/// Length of a VLAN tag.
+const VLAN_TAG_LEN: u32 = 4;
+
+/// ICMP protocol number.
+const IPPROTO_ICMP: u32 = 1;
+/// TCP protocol number.
+const IPPROTO_TCP: u32 = 6;
+/// UDP protocol number.
+const IPPROTO_UDP: u32 = 17;
+/// ICMPv6 protocol number.
+const IPPROTO_ICMPV6: u32 = 58;
+/// SCTP protocol number.
+const IPPROTO_SCTP: u32 = 132;
+
+/// Value loaded in the accumulator.
+#[derive(Clone, Copy)]
+enum Load {
+    /// Value of the given size at an absolute offset.
+    Abs(u16, u32),
+    /// Value of the given size at an offset from the end of the IPv4 header starting at
+    /// `ip_offset`.
+    Ip4Payload(u16, u32, u32),
+    /// Original length of the packet.
+    Len,
+}
+
+/// Boolean expression on the content of a packet.
+enum Expr {
+    /// Constant.
+    Bool(bool),
+    /// Check a masked loaded value against a constant with a jump condition.
+    Test {
+        load: Load,
+        mask: u32,
+        cond: u16,
+        value: u32,
+    },
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+/// Compare a loaded value with a constant.
+fn test(load: Load, cond: u16, value: u32) -> Expr {
+    Expr::Test {
+        load,
+        mask: u32::MAX,
+        cond,
+        value,
+    }
+}
+
+/// Compare a masked loaded value with a constant.
+fn test_masked(load: Load, mask: u32, value: u32) -> Expr {
+    Expr::Test {
+        load,
+        mask,
+        cond: BPF_JEQ,
+        value: value & mask,
+    }
+}
+
+fn and(a: Expr, b: Expr) -> Expr {
+    Expr::And(Box::new(a), Box::new(b))
+}
+
+fn or(a: Expr, b: Expr) -> Expr {
+    Expr::Or(Box::new(a), Box::new(b))
+}
+
+fn not(a: Expr) -> Expr {
+    Expr::Not(Box::new(a))
+}
+
+/// Direction qualifier.
+#[derive(Clone, Copy)]
+enum Dir {
+    Src,
+    Dst,
+    SrcOrDst,
+    SrcAndDst,
+}
+
+impl Dir {
+    /// Build the expression of a primitive given its source and destination expressions.
+    fn apply(self, f: impl Fn(bool) -> Expr) -> Expr {
+        match self {
+            Dir::Src => f(true),
+            Dir::Dst => f(false),
+            Dir::SrcOrDst => or(f(true), f(false)),
+            Dir::SrcAndDst => and(f(true), f(false)),
+        }
+    }
+}
+
+/// Compare the bytes at the given offset with the first `prefix_len` bits of `bytes`.
+fn bytes_test(offset: u32, bytes: &[u8], prefix_len: u32) -> Expr {
+    let mut expr = Expr::Bool(true);
+
+    for (i, chunk) in bytes.chunks(4).enumerate() {
+        let bits = (chunk.len() * 8) as u32;
+        let chunk_prefix_len = prefix_len.saturating_sub(i as u32 * 32).min(bits);
+        if chunk_prefix_len == 0 {
+            break;
+        }
+
+        let size = if chunk.len() == 4 { BPF_W } else { BPF_H };
+        let value = chunk
+            .iter()
+            .fold(0, |value, b| (value << 8) | u32::from(*b));
+        let mask = (((1u64 << chunk_prefix_len) - 1) << (bits - chunk_prefix_len)) as u32;
+        let chunk_test = test_masked(Load::Abs(size, offset + i as u32 * 4), mask, value);
+
+        expr = match expr {
+            Expr::Bool(true) => chunk_test,
+            expr => and(expr, chunk_test),
+        };
+    }
+
+    expr
+}
+
+/// Parse a number, in decimal or hexadecimal ("0x" prefix) notation.
+fn parse_number(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Split a filter expression in tokens.
+fn tokenize(expression: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut rest = expression.trim_start();
+
+    while !rest.is_empty() {
+        let len = if rest.starts_with("&&") || rest.starts_with("||") {
+            2
+        } else if rest.starts_with(['(', ')', '!']) {
+            1
+        } else {
+            rest.find(|c: char| c.is_whitespace() || "()!&|".contains(c))
+                .unwrap_or(rest.len())
+                .max(1)
+        };
+
+        tokens.push(&rest[..len]);
+        rest = rest[len..].trim_start();
+    }
+
+    tokens
+}
+
+/// Recursive descent parser of filter expressions.
+struct Parser<'a> {
+    /// Tokens of the expression.
+    tokens: Vec<&'a str>,
+    /// Index of the next token.
+    pos: usize,
+    /// Offset of the network layer header, which is moved forward by `vlan`.
+    l3_offset: u32,
+}
+
+impl<'a> Parser<'a> {
+    /// Return the next token, if any.
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    /// Consume the next token if it is one of the given ones.
+    fn accept(&mut self, tokens: &[&str]) -> Option<&'a str> {
+        let token = self.peek().filter(|token| tokens.contains(token))?;
+        self.pos += 1;
+        Some(token)
+    }
+
+    /// Consume the next token, which must exist.
+    fn next(&mut self, what: &str) -> Result<&'a str, String> {
+        let token = self
+            .peek()
+            .ok_or_else(|| format!("Invalid filter: missing {}", what))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    /// Consume the next token, which must be a number.
+    fn number(&mut self) -> Result<u32, String> {
+        let token = self.next("number")?;
+        parse_number(token).ok_or_else(|| format!("Invalid filter: invalid number '{}'", token))
+    }
+
+    /// Check the ethertype.
+    fn ethertype(&self, ethertype: u32) -> Expr {
+        test(Load::Abs(BPF_H, self.l3_offset - 2), BPF_JEQ, ethertype)
+    }
+
+    /// Check the protocol of IPv4 packets.
+    fn ip_proto(&self, proto: u32) -> Expr {
+        and(
+            self.ethertype(ETHERTYPE_IPV4.into()),
+            test(Load::Abs(BPF_B, self.l3_offset + 9), BPF_JEQ, proto),
+        )
+    }
+
+    /// Check the next header of IPv6 packets.
+    fn ip6_proto(&self, proto: u32) -> Expr {
+        and(
+            self.ethertype(ETHERTYPE_IPV6.into()),
+            test(Load::Abs(BPF_B, self.l3_offset + 6), BPF_JEQ, proto),
+        )
+    }
+
+    /// Parse `or` expressions.
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_and()?;
+        while self.accept(&["or", "||"]).is_some() {
+            expr = or(expr, self.parse_and()?);
+        }
+
+        Ok(expr)
+    }
+
+    /// Parse `and` expressions.
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_not()?;
+        while self.accept(&["and", "&&"]).is_some() {
+            expr = and(expr, self.parse_not()?);
+        }
+
+        Ok(expr)
+    }
+
+    /// Parse negations, parentheses and primitives.
+    fn parse_not(&mut self) -> Result<Expr, String> {
+        if self.accept(&["not", "!"]).is_some() {
+            return Ok(not(self.parse_not()?));
+        }
+
+        if self.accept(&["("]).is_some() {
+            let expr = self.parse_or()?;
+            if self.accept(&[")"]).is_none() {
+                return Err("Invalid filter: missing ')'".to_string());
+            }
+            return Ok(expr);
+        }
+
+        self.parse_primitive()
+    }
+
+    /// Parse a primitive.
+    fn parse_primitive(&mut self) -> Result<Expr, String> {
+        match self.accept(&["less", "greater", "vlan"]) {
+            Some("less") => return Ok(not(test(Load::Len, BPF_JGT, self.number()?))),
+            Some("greater") => return Ok(test(Load::Len, BPF_JGE, self.number()?)),
+            Some(_) => return Ok(self.parse_vlan()),
+            None => {}
+        }
+
+        let proto = self.accept(&[
+            "ether", "ip", "ip6", "arp", "tcp", "udp", "sctp", "icmp", "icmp6",
+        ]);
+        let dir = match self.accept(&["src", "dst"]) {
+            Some("src") if self.peek_pair("or", "dst") => Some(Dir::SrcOrDst),
+            Some("src") if self.peek_pair("and", "dst") => Some(Dir::SrcAndDst),
+            Some("src") => Some(Dir::Src),
+            Some(_) => Some(Dir::Dst),
+            None => None,
+        };
+        let kind = self.accept(&["host", "net", "port", "portrange", "proto"]);
+
+        match (proto, dir, kind) {
+            (Some(proto), None, None) => self.protocol(proto),
+            (proto, None, Some("proto")) => self.parse_proto(proto),
+            (proto, dir, Some("port" | "portrange")) => self.parse_port(
+                proto,
+                dir.unwrap_or(Dir::SrcOrDst),
+                kind == Some("portrange"),
+            ),
+            (proto, dir, Some("net")) => self.parse_host(proto, dir.unwrap_or(Dir::SrcOrDst), true),
+            (proto, Some(dir), None) | (proto, Some(dir), Some("host")) => {
+                self.parse_host(proto, dir, false)
+            }
+            (proto, None, Some("host")) => self.parse_host(proto, Dir::SrcOrDst, false),
+            _ => Err(match self.peek() {
+                Some(token) => format!("Invalid filter: unexpected '{}'", token),
+                None => "Invalid filter: unexpected end of expression".to_string(),
+            }),
+        }
+    }
+
+    /// Check whether the next two tokens are `first` and `second`, consuming them if so.
+    fn peek_pair(&mut self, first: &str, second: &str) -> bool {
+        let found = self.tokens.get(self.pos..self.pos + 2) == Some(&[first, second][..]);
+        if found {
+            self.pos += 2;
+        }
+
+        found
+    }
+
+    /// Build the expression of a bare protocol.
+    fn protocol(&self, proto: &str) -> Result<Expr, String> {
+        Ok(match proto {
+            "ip" => self.ethertype(ETHERTYPE_IPV4.into()),
+            "ip6" => self.ethertype(ETHERTYPE_IPV6.into()),
+            "arp" => self.ethertype(ETHERTYPE_ARP.into()),
+            "tcp" => or(self.ip_proto(IPPROTO_TCP), self.ip6_proto(IPPROTO_TCP)),
+            "udp" => or(self.ip_proto(IPPROTO_UDP), self.ip6_proto(IPPROTO_UDP)),
+            "sctp" => or(self.ip_proto(IPPROTO_SCTP), self.ip6_proto(IPPROTO_SCTP)),
+            "icmp" => self.ip_proto(IPPROTO_ICMP),
+            "icmp6" => self.ip6_proto(IPPROTO_ICMPV6),
+            _ => return Err(format!("Invalid filter: '{}' requires a qualifier", proto)),
+        })
+    }
+
+    /// Parse `vlan [ID]`.
+    fn parse_vlan(&mut self) -> Expr {
+        let mut expr = or(
+            self.ethertype(ETHERTYPE_VLAN.into()),
+            self.ethertype(ETHERTYPE_QINQ.into()),
+        );
+        if let Some(id) = self.peek().and_then(parse_number) {
+            self.pos += 1;
+            expr = and(
+                expr,
+                test_masked(Load::Abs(BPF_H, self.l3_offset), 0x0fff, id),
+            );
+        }
+
+        self.l3_offset += VLAN_TAG_LEN;
+        expr
+    }
+
+    /// Parse `[ether|ip|ip6] proto PROTO`.
+    fn parse_proto(&mut self, proto: Option<&str>) -> Result<Expr, String> {
+        let token = self.next("protocol")?;
+        let number = parse_number(token).or(match (proto, token) {
+            (Some("ether"), "ip") => Some(u32::from(ETHERTYPE_IPV4)),
+            (Some("ether"), "ip6") => Some(u32::from(ETHERTYPE_IPV6)),
+            (Some("ether"), "arp") => Some(u32::from(ETHERTYPE_ARP)),
+            (Some("ether"), _) => None,
+            (_, "icmp") => Some(IPPROTO_ICMP),
+            (_, "tcp") => Some(IPPROTO_TCP),
+            (_, "udp") => Some(IPPROTO_UDP),
+            (_, "icmp6") => Some(IPPROTO_ICMPV6),
+            (_, "sctp") => Some(IPPROTO_SCTP),
+            _ => None,
+        });
+        let number =
+            number.ok_or_else(|| format!("Invalid filter: unknown protocol '{}'", token))?;
+
+        match proto {
+            Some("ether") => Ok(self.ethertype(number)),
+            Some("ip") => Ok(self.ip_proto(number)),
+            Some("ip6") => Ok(self.ip6_proto(number)),
+            None => Ok(or(self.ip_proto(number), self.ip6_proto(number))),
+            Some(proto) => Err(format!(
+                "Invalid filter: '{} proto' is not supported",
+                proto
+            )),
+        }
+    }
+
+    /// Parse `[tcp|udp|sctp] [src|dst] port PORT` and `portrange PORT-PORT`.
+    fn parse_port(&mut self, proto: Option<&str>, dir: Dir, range: bool) -> Result<Expr, String> {
+        let (low, high) = if range {
+            let token = self.next("port range")?;
+            token
+                .split_once('-')
+                .and_then(|(low, high)| Some((parse_number(low)?, parse_number(high)?)))
+                .ok_or_else(|| format!("Invalid filter: invalid port range '{}'", token))?
+        } else {
+            let port = self.number()?;
+            (port, port)
+        };
+
+        let protos: &[u32] = match proto {
+            Some("tcp") => &[IPPROTO_TCP],
+            Some("udp") => &[IPPROTO_UDP],
+            Some("sctp") => &[IPPROTO_SCTP],
+            None => &[IPPROTO_TCP, IPPROTO_UDP, IPPROTO_SCTP],
+            Some(proto) => {
+                return Err(format!("Invalid filter: '{} port' is not supported", proto))
+            }
+        };
+
+        let range_test = |load: Load| {
+            if low == high {
+                test(load, BPF_JEQ, low)
+            } else {
+                and(test(load, BPF_JGE, low), not(test(load, BPF_JGT, high)))
+            }
+        };
+        let any_proto = |offset: u32| {
+            protos
+                .iter()
+                .map(|proto| test(Load::Abs(BPF_B, offset), BPF_JEQ, *proto))
+                .reduce(or)
+                .unwrap()
+        };
+        let l3_offset = self.l3_offset;
+
+        // Transport headers are only available in the first IPv4 fragment.
+        let ipv4 = and(
+            and(
+                self.ethertype(ETHERTYPE_IPV4.into()),
+                any_proto(l3_offset + 9),
+            ),
+            and(
+                not(test(Load::Abs(BPF_H, l3_offset + 6), BPF_JSET, 0x1fff)),
+                dir.apply(|src| {
+                    range_test(Load::Ip4Payload(BPF_H, l3_offset, if src { 0 } else { 2 }))
+                }),
+            ),
+        );
+        // Only the transport headers directly following the IPv6 header are checked.
+        let ipv6 = and(
+            and(
+                self.ethertype(ETHERTYPE_IPV6.into()),
+                any_proto(l3_offset + 6),
+            ),
+            dir.apply(|src| {
+                let offset = l3_offset + IPV6_HEADER_LEN + if src { 0 } else { 2 };
+                range_test(Load::Abs(BPF_H, offset))
+            }),
+        );
+
+        Ok(or(ipv4, ipv6))
+    }
+
+    /// Parse `[ether|ip|ip6|arp] [src|dst] host ADDR` and `[ip|ip6] [src|dst] net ADDR/LEN`.
+    fn parse_host(&mut self, proto: Option<&str>, dir: Dir, net: bool) -> Result<Expr, String> {
+        let token = self.next("address")?;
+        let invalid = || format!("Invalid filter: invalid address '{}'", token);
+
+        let (addr, prefix_len) = match token.split_once('/') {
+            Some((addr, prefix_len)) if net => (addr, prefix_len.parse().map_err(|_| invalid())?),
+            Some(_) => return Err(invalid()),
+            None => (token, u32::MAX),
+        };
+
+        if !net && (proto == Some("ether") || (proto.is_none() && addr.parse::<IpAddr>().is_err()))
+        {
+            let mac = link::parse_mac(addr).map_err(|_| invalid())?;
+            return Ok(dir.apply(|src| bytes_test(if src { 6 } else { 0 }, &mac, u32::MAX)));
+        }
+
+        let addr = addr.parse::<IpAddr>().map_err(|_| invalid())?;
+        let (ethertype, octets, src_offset, dst_offset) = match (proto, addr) {
+            (None | Some("ip"), IpAddr::V4(addr)) => {
+                (ETHERTYPE_IPV4, addr.octets().to_vec(), 12, 16)
+            }
+            (Some("arp"), IpAddr::V4(addr)) if !net => {
+                (ETHERTYPE_ARP, addr.octets().to_vec(), 14, 24)
+            }
+            (None | Some("ip6"), IpAddr::V6(addr)) => {
+                (ETHERTYPE_IPV6, addr.octets().to_vec(), 8, 24)
+            }
+            (Some("ip" | "ip6" | "arp"), _) => return Err(invalid()),
+            (Some(proto), _) => {
+                return Err(format!(
+                    "Invalid filter: '{} {}' is not supported",
+                    proto,
+                    if net { "net" } else { "host" }
+                ))
+            }
+        };
+        if prefix_len != u32::MAX && prefix_len > octets.len() as u32 * 8 {
+            return Err(invalid());
+        }
+
+        let l3_offset = self.l3_offset;
+        Ok(and(
+            self.ethertype(ethertype.into()),
+            dir.apply(|src| {
+                let offset = l3_offset + if src { src_offset } else { dst_offset };
+                bytes_test(offset, &octets, prefix_len)
+            }),
+        ))
+    }
+}
+
+/// Label of an instruction of a program being generated.
+type Label = usize;
+
+/// Instruction of a program being generated, whose jumps refer to labels.
+enum Op {
+    Stmt(Instruction),
+    Jump(u16, u32, Label, Label),
+    Goto(Label),
+}
+
+/// Generator of the BPF program of an expression.
+#[derive(Default)]
+struct Codegen {
+    /// Generated instructions.
+    ops: Vec<Op>,
+    /// Position of each label.
+    labels: Vec<usize>,
+}
+
+impl Codegen {
+    /// Create a new label.
+    fn label(&mut self) -> Label {
+        self.labels.push(usize::MAX);
+        self.labels.len() - 1
+    }
+
+    /// Place a label at the next instruction.
+    fn place(&mut self, label: Label) {
+        self.labels[label] = self.ops.len();
+    }
+
+    /// Generate the code jumping to `t` if `expr` is true, to `f` otherwise.
+    fn cond(&mut self, expr: &Expr, t: Label, f: Label) {
+        match expr {
+            Expr::Bool(value) => self.ops.push(Op::Goto(if *value { t } else { f })),
+            Expr::Test {
+                load,
+                mask,
+                cond,
+                value,
+            } => {
+                match *load {
+                    Load::Abs(size, offset) => self
+                        .ops
+                        .push(Op::Stmt(Instruction::stmt(BPF_LD | size | BPF_ABS, offset))),
+                    Load::Ip4Payload(size, ip_offset, offset) => {
+                        self.ops.push(Op::Stmt(Instruction::stmt(
+                            BPF_LDX | BPF_B | BPF_MSH,
+                            ip_offset,
+                        )));
+                        self.ops.push(Op::Stmt(Instruction::stmt(
+                            BPF_LD | size | BPF_IND,
+                            ip_offset + offset,
+                        )));
+                    }
+                    Load::Len => self
+                        .ops
+                        .push(Op::Stmt(Instruction::stmt(BPF_LD | BPF_W | BPF_LEN, 0))),
+                }
+                if *mask != u32::MAX {
+                    self.ops.push(Op::Stmt(Instruction::stmt(
+                        BPF_ALU | BPF_AND | BPF_K,
+                        *mask,
+                    )));
+                }
+                self.ops
+                    .push(Op::Jump(BPF_JMP | cond | BPF_K, *value, t, f));
+            }
+            Expr::And(a, b) => {
+                let next = self.label();
+                self.cond(a, next, f);
+                self.place(next);
+                self.cond(b, t, f);
+            }
+            Expr::Or(a, b) => {
+                let next = self.label();
+                self.cond(a, t, next);
+                self.place(next);
+                self.cond(b, t, f);
+            }
+            Expr::Not(a) => self.cond(a, f, t),
+        }
+    }
+
+    /// Jump through trampolines (unconditional jumps placed right after the conditional ones)
+    /// to the labels out of reach of conditional jumps, whose offsets are limited to 255.
+    fn add_trampolines(&mut self) {
+        let mut pc = 0;
+        let mut changed = false;
+
+        loop {
+            if pc == self.ops.len() {
+                // The trampolines may push the targets of the previous jumps out of reach.
+                if !changed {
+                    break;
+                }
+                pc = 0;
+                changed = false;
+            }
+
+            let Op::Jump(code, k, t, f) = self.ops[pc] else {
+                pc += 1;
+                continue;
+            };
+
+            let mut targets = [t, f];
+            let mut trampolines = Vec::new();
+            for target in targets.iter_mut() {
+                // The trampolines inserted so far move the targets away.
+                if self.labels[*target] - (pc + 1) + trampolines.len() > u8::MAX as usize {
+                    let trampoline = self.label();
+                    self.labels[trampoline] = pc + 1 + trampolines.len();
+                    trampolines.push(Op::Goto(*target));
+                    *target = trampoline;
+                }
+            }
+            if trampolines.is_empty() {
+                pc += 1;
+                continue;
+            }
+
+            // Move the instructions after the jump, and their labels.
+            let count = trampolines.len();
+            let first_trampoline = self.labels.len() - count;
+            for label in &mut self.labels[..first_trampoline] {
+                if *label > pc {
+                    *label += count;
+                }
+            }
+            self.ops[pc] = Op::Jump(code, k, targets[0], targets[1]);
+            self.ops.splice(pc + 1..pc + 1, trampolines);
+
+            pc += 1 + count;
+            changed = true;
+        }
+    }
+
+    /// Resolve the labels and return the program (the offsets of the conditional jumps fit
+    /// once the trampolines are added).
+    fn finish(mut self) -> Vec<Instruction> {
+        self.add_trampolines();
+        let offset = |pc: usize, label: Label| self.labels[label] - (pc + 1);
+
+        self.ops
+            .iter()
+            .enumerate()
+            .map(|(pc, op)| match *op {
+                Op::Stmt(insn) => insn,
+                Op::Jump(code, k, t, f) => {
+                    Instruction::jump(code, k, offset(pc, t) as u8, offset(pc, f) as u8)
+                }
+                Op::Goto(label) => Instruction::stmt(BPF_JMP | BPF_JA, offset(pc, label) as u32),
+            })
+            .collect()
+    }
+}
+
+/// Filter of the packets to replay.
+#[derive(Clone, Debug)]
+pub struct Filter {
+    /// Compiled BPF program.
+    program: Vec<Instruction>,
+}
+
+impl Filter {
+    /// Compile a filter expression.
+    pub fn compile(expression: &str) -> Result<Filter, String> {
+        let mut parser = Parser {
+            tokens: tokenize(expression),
+            pos: 0,
+            l3_offset: ETHERNET_HEADER_LEN,
+        };
+        let expr = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("Invalid filter: unexpected '{}'", token));
+        }
+
+        let mut codegen = Codegen::default();
+        let (t, f) = (codegen.label(), codegen.label());
+        codegen.cond(&expr, t, f);
+        codegen.place(t);
+        codegen
+            .ops
+            .push(Op::Stmt(Instruction::stmt(BPF_RET | BPF_K, SNAPLEN)));
+        codegen.place(f);
+        codegen
+            .ops
+            .push(Op::Stmt(Instruction::stmt(BPF_RET | BPF_K, 0)));
+
+        Ok(Filter {
+            program: codegen.finish(),
+        })
+    }
+
+    /// Check whether an Ethernet frame, whose original length is `wire_len`, matches the filter.
+    pub fn matches(&self, frame: &[u8], wire_len: usize) -> bool {
+        bpf::run(
+            &self.program,
+            frame,
+            wire_len.try_into().unwrap_or(u32::MAX),
+        ) > 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filter::Filter;
+
+    /// Ethernet + IPv4 + TCP/UDP frame.
+    fn ipv4_frame(proto: u8, src: [u8; 4], dst: [u8; 4], sport: u16, dport: u16) -> Vec<u8> {
+        let mut frame = vec![0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+        frame.extend_from_slice(&[0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00]);
+        // IP header with options.
+        frame.extend_from_slice(&[0x46, 0, 0, 48, 0, 0, 0x40, 0, 64, proto, 0, 0]);
+        frame.extend_from_slice(&src);
+        frame.extend_from_slice(&dst);
+        frame.extend_from_slice(&[1, 1, 1, 1]);
+        frame.extend_from_slice(&sport.to_be_bytes());
+        frame.extend_from_slice(&dport.to_be_bytes());
+        frame.resize(frame.len() + 16, 0);
+        frame
+    }
+
+    /// Ethernet + IPv6 + UDP frame.
+    fn ipv6_frame(src_last: u8, sport: u16, dport: u16) -> Vec<u8> {
+        let mut frame = vec![0; 12];
+        frame.extend_from_slice(&[0x86, 0xdd, 0x60, 0, 0, 0, 0, 8, 17, 64]);
+        frame.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+        frame.extend_from_slice(&[0; 11]);
+        frame.push(src_last);
+        frame.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+        frame.extend_from_slice(&[0; 11]);
+        frame.push(2);
+        frame.extend_from_slice(&sport.to_be_bytes());
+        frame.extend_from_slice(&dport.to_be_bytes());
+        frame.extend_from_slice(&[0; 4]);
+        frame
+    }
+
+    /// Check whether a frame matches a filter expression.
+    fn matches(expression: &str, frame: &[u8]) -> bool {
+        Filter::compile(expression)
+            .unwrap()
+            .matches(frame, frame.len())
+    }
+
+    #[test]
+    fn test_filter() {
+        let tcp = ipv4_frame(6, [10, 0, 0, 5], [10, 1, 0, 1], 50000, 443);
+        let udp = ipv4_frame(17, [192, 168, 1, 1], [10, 0, 0, 5], 53, 1234);
+        let udp6 = ipv6_frame(1, 5353, 53);
+
+        assert!(matches("tcp port 443 and host 10.0.0.5", &tcp));
+        assert!(!matches("tcp port 443 and host 10.0.0.5", &udp));
+        assert!(matches("ip && !tcp", &udp));
+        assert!(matches("src host 10.0.0.5 and dst port 443", &tcp));
+        assert!(!matches("dst host 10.0.0.5", &tcp));
+        assert!(matches("src or dst host 10.1.0.1", &tcp));
+        assert!(!matches("src and dst host 10.1.0.1", &tcp));
+        assert!(matches("net 10.1.0.0/16", &tcp));
+        assert!(!matches("src net 10.1.0.0/16", &tcp));
+        assert!(matches("udp src port 53", &udp));
+        assert!(matches("portrange 1000-2000", &udp));
+        assert!(!matches("portrange 2000-3000", &udp));
+        assert!(matches("ip proto 17 and not (tcp or icmp)", &udp));
+        assert!(matches("ether src host 66:77:88:99:aa:bb", &tcp));
+        assert!(!matches("ether dst 66:77:88:99:aa:bb", &tcp));
+        assert!(matches("less 100 and greater 50", &tcp));
+        assert!(!matches("greater 60", &tcp));
+        assert!(!matches("vlan", &tcp));
+
+        // IPv6.
+        assert!(matches("ip6 and udp port 53", &udp6));
+        assert!(!matches("ip", &udp6));
+        assert!(matches("src host 2001:db8::1", &udp6));
+        assert!(!matches("src host 2001:db8::2", &udp6));
+        assert!(matches("dst net 2001:db8::/32", &udp6));
+        assert!(!matches("tcp", &udp6));
+
+        // Fragments other than the first one have no transport header.
+        let mut fragment = tcp.clone();
+        fragment[20] = 0x01;
+        assert!(matches("tcp", &fragment));
+        assert!(!matches("tcp port 443", &fragment));
+
+        // VLAN tagged frames.
+        let mut tagged = tcp[..12].to_vec();
+        tagged.extend_from_slice(&[0x81, 0x00, 0x00, 0x64]);
+        tagged.extend_from_slice(&tcp[12..]);
+        assert!(matches("vlan 100 and tcp port 443", &tagged));
+        assert!(!matches("vlan 101 and tcp port 443", &tagged));
+        assert!(!matches("tcp port 443", &tagged));
+
+        // Jumps beyond the reach of the conditional jumps.
+        let ports = (1..=200)
+            .map(|port| format!("port {}", port))
+            .collect::<Vec<_>>()
+            .join(" or ");
+        assert!(!matches(&ports, &tcp));
+        assert!(matches(&ports, &udp));
+        assert!(matches(&format!("({}) and udp", ports), &udp));
+        assert!(!matches(&format!("({}) and tcp", ports), &udp));
+        assert!(matches(&format!("not ({}) and tcp", ports), &tcp));
+
+        // Invalid expressions.
+        for expression in [
+            "",
+            "tcp port",
+            "host 10.0.0",
+            "(tcp",
+            "tcp udp",
+            "ip port 80",
+            "tcp host 10.0.0.5",
+            "net 10.0.0.0/33",
+            "foo",
+        ] {
+            assert!(Filter::compile(expression).is_err(), "{}", expression);
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! Handle packets captured partially (captured length smaller than the original length).
+
+use clap::ValueEnum;
+
+use crate::link::{self, ETHERTYPE_IPV4, ETHERTYPE_IPV6};
+
+/// TCP protocol number.
+const IPPROTO_TCP: u8 = 6;
+/// UDP protocol number.
+const IPPROTO_UDP: u8 = 17;
+
+/// Length of the IPv6 header.
+const IPV6_HEADER_LEN: usize = 40;
+/// Length of the UDP header.
+const UDP_HEADER_LEN: usize = 8;
+/// Minimum length of the TCP header.
+const TCP_HEADER_LEN: usize = 20;
+
+/// Policy for packets captured partially.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum FixLen {
+    /// Send the packets as captured.
+    None,
+    /// Pad the packets with zeros up to their original length.
+    Pad,
+    /// Fix the IP/UDP/TCP lengths (and checksums) to match the captured length.
+    Trunc,
+}
+
+/// Write a big endian u16 at the given offset.
+fn write_u16(data: &mut [u8], offset: usize, value: u16) {
+    data[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+/// Sum data as big endian u16 words, as required by the Internet checksum.
+fn checksum_add(mut sum: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    for chunk in &mut chunks {
+        sum += u32::from(u16::from_be_bytes([chunk[0], chunk[1]]));
+    }
+    if let [last] = chunks.remainder() {
+        sum += u32::from(*last) << 8;
+    }
+
+    sum
+}
+
+/// Fold a sum into the Internet checksum.
+fn checksum_fold(mut sum: u32) -> u16 {
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+/// Recompute the checksum of a UDP/TCP segment, given the sum of its pseudo header.
+fn fix_l4(segment: &mut [u8], protocol: u8, pseudo_sum: u32) {
+    let checksum_offset = match protocol {
+        IPPROTO_UDP if segment.len() >= UDP_HEADER_LEN => {
+            write_u16(segment, 4, segment.len() as u16);
+            // A zero UDP checksum means the checksum is disabled.
+            if link::read_u16_be(segment, 6) == Some(0) {
+                return;
+            }
+            6
+        }
+        IPPROTO_TCP if segment.len() >= TCP_HEADER_LEN => 16,
+        _ => return,
+    };
+
+    write_u16(segment, checksum_offset, 0);
+    let sum = checksum_add(
+        pseudo_sum + u32::from(protocol) + segment.len() as u32,
+        segment,
+    );
+    let checksum = match checksum_fold(sum) {
+        // A computed UDP checksum of zero is transmitted as all ones.
+        0 if protocol == IPPROTO_UDP => 0xffff,
+        checksum => checksum,
+    };
+    write_u16(segment, checksum_offset, checksum);
+}
+
+/// Fix the lengths and checksums of an IPv4 packet.
+fn fix_ipv4(packet: &mut [u8]) {
+    if packet.len() < 20 {
+        return;
+    }
+
+    let header_len = ((packet[0] & 0x0f) as usize) * 4;
+    if header_len < 20 || packet.len() < header_len {
+        return;
+    }
+
+    write_u16(packet, 2, packet.len() as u16);
+    write_u16(packet, 10, 0);
+    let checksum = checksum_fold(checksum_add(0, &packet[..header_len]));
+    write_u16(packet, 10, checksum);
+
+    // Transport headers are only available in the first fragment, whose length fields refer to
+    // the whole datagram.
+    let fragmented = link::read_u16_be(packet, 6).is_some_and(|flags| flags & 0x3fff != 0);
+    if !fragmented {
+        let protocol = packet[9];
+        let pseudo_sum = checksum_add(0, &packet[12..20]);
+        fix_l4(&mut packet[header_len..], protocol, pseudo_sum);
+    }
+}
+
+/// Fix the lengths and checksums of an IPv6 packet.
+/// Only the transport headers directly following the IPv6 header are fixed.
+fn fix_ipv6(packet: &mut [u8]) {
+    if packet.len() < IPV6_HEADER_LEN {
+        return;
+    }
+
+    write_u16(packet, 4, (packet.len() - IPV6_HEADER_LEN) as u16);
+
+    let protocol = packet[6];
+    let pseudo_sum = checksum_add(0, &packet[8..IPV6_HEADER_LEN]);
+    fix_l4(&mut packet[IPV6_HEADER_LEN..], protocol, pseudo_sum);
+}
+
+/// Fix the IP/UDP/TCP lengths and checksums of an Ethernet frame to match its length.
+fn fix_ethernet(frame: &mut [u8]) {
+    let (ethertype, offset) = match link::parse_ethernet(frame) {
+        Some(payload) => payload,
+        None => return,
+    };
+
+    match ethertype {
+        ETHERTYPE_IPV4 => fix_ipv4(&mut frame[offset..]),
+        ETHERTYPE_IPV6 => fix_ipv6(&mut frame[offset..]),
+        _ => {}
+    }
+}
+
+/// Apply the `FixLen` policy to Ethernet frames.
+pub struct LengthFixer {
+    /// Policy.
+    policy: FixLen,
+    /// Buffer holding the last fixed frame.
+    buffer: Vec<u8>,
+}
+
+impl LengthFixer {
+    /// Construct a fixer applying the given policy.
+    pub fn new(policy: FixLen) -> LengthFixer {
+        LengthFixer {
+            policy,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Fix a frame which is `missing` bytes shorter than originally.
+    pub fn fix<'a>(&'a mut self, frame: &'a [u8], missing: usize) -> &'a [u8] {
+        if missing == 0 || self.policy == FixLen::None {
+            return frame;
+        }
+
+        self.buffer.clear();
+        self.buffer.extend_from_slice(frame);
+
+        match self.policy {
+            FixLen::Pad => self.buffer.resize(frame.len() + missing, 0),
+            FixLen::Trunc => fix_ethernet(&mut self.buffer),
+            FixLen::None => unreachable!(),
+        }
+
+        &self.buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixlen::{checksum_add, checksum_fold, FixLen, LengthFixer};
+
+    /// Ethernet + IPv4 + UDP frame with 8 bytes of payload and valid checksums.
+    fn udp_frame() -> Vec<u8> {
+        let mut frame = vec![0; 14];
+        frame[12..].copy_from_slice(&[0x08, 0x00]);
+        frame.extend_from_slice(&[
+            0x45, 0x00, 0x00, 0x24, 0x00, 0x01, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00, 10, 0, 0, 1,
+            10, 0, 0, 2,
+        ]);
+        frame.extend_from_slice(&[0x04, 0xd2, 0x16, 0x2e, 0x00, 0x10, 0x00, 0x00]);
+        frame.extend_from_slice(b"abcdefgh");
+
+        let ip_checksum = checksum_fold(checksum_add(0, &frame[14..34]));
+        frame[24..26].copy_from_slice(&ip_checksum.to_be_bytes());
+        let udp_sum = checksum_add(checksum_add(17 + 16, &frame[26..34]), &frame[34..]);
+        frame[40..42].copy_from_slice(&checksum_fold(udp_sum).to_be_bytes());
+
+        frame
+    }
+
+    #[test]
+    fn test_fix_none_and_pad() {
+        let frame = udp_frame();
+
+        assert_eq!(
+            LengthFixer::new(FixLen::None).fix(&frame[..40], 10),
+            &frame[..40]
+        );
+        assert_eq!(LengthFixer::new(FixLen::Pad).fix(&frame, 0), &frame[..]);
+
+        let mut fixer = LengthFixer::new(FixLen::Pad);
+        let padded = fixer.fix(&frame[..46], 4);
+        assert_eq!(padded.len(), 50);
+        assert_eq!(&padded[..46], &frame[..46]);
+        assert_eq!(&padded[46..], &[0; 4]);
+    }
+
+    #[test]
+    fn test_fix_trunc() {
+        let frame = udp_frame();
+        let mut fixer = LengthFixer::new(FixLen::Trunc);
+        let fixed = fixer.fix(&frame[..46], 4);
+
+        // IP and UDP lengths.
+        assert_eq!(&fixed[16..18], &[0x00, 0x20]);
+        assert_eq!(&fixed[38..40], &[0x00, 0x0c]);
+
+        // Checksums are valid.
+        assert_eq!(checksum_fold(checksum_add(0, &fixed[14..34])), 0);
+        let udp_sum = checksum_add(checksum_add(17 + 12, &fixed[26..34]), &fixed[34..]);
+        assert_eq!(checksum_fold(udp_sum), 0);
+
+        // The payload is untouched.
+        assert_eq!(&fixed[42..], b"abcd");
+    }
+}
@@ -0,0 +1,239 @@
+//! Open PCAP inputs, transparently decompressing them if needed.
+
+use std::{
+    fs::{self, File},
+    io::{stdin, BufRead, BufReader, Error, ErrorKind, Read, Result},
+    path::{Path, PathBuf},
+};
+
+use flate2::read::MultiGzDecoder;
+use memmap2::Mmap;
+use xz2::read::XzDecoder;
+use zstd::stream::read::Decoder as ZstdDecoder;
+
+/// Path used to read a PCAP from the standard input.
+pub const STDIN_PATH: &str = "-";
+
+/// Magic bytes of a gzip stream.
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+/// Magic bytes of a xz stream.
+const XZ_MAGIC: &[u8] = &[0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00];
+/// Magic bytes of a zstd frame.
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Check whether data starts with the magic bytes of a supported compression format.
+pub fn is_compressed(data: &[u8]) -> bool {
+    [GZIP_MAGIC, XZ_MAGIC, ZSTD_MAGIC]
+        .iter()
+        .any(|magic| data.starts_with(magic))
+}
+
+/// Wrap a reader into the proper decompressor, detecting the compression from its magic bytes.
+/// Uncompressed data is returned as is.
+pub fn decompress<'a, R: Read + 'a>(reader: R) -> Result<Box<dyn Read + 'a>> {
+    let mut reader = BufReader::new(reader);
+    let magic = reader.fill_buf()?;
+
+    if magic.starts_with(GZIP_MAGIC) {
+        Ok(Box::new(MultiGzDecoder::new(reader)))
+    } else if magic.starts_with(XZ_MAGIC) {
+        Ok(Box::new(XzDecoder::new_multi_decoder(reader)))
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        Ok(Box::new(ZstdDecoder::with_buffer(reader)?))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Check whether a file name ends with one of the given extensions (e.g. "pcap" or "pcap.gz").
+fn has_extension(path: &Path, extensions: &[String]) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    extensions
+        .iter()
+        .any(|ext| name.ends_with(&format!(".{}", ext.trim_start_matches('.'))))
+}
+
+/// Recursively collect the files in a directory, sorted by path.
+/// The symbolic links to directories are skipped, since they may create loops.
+fn walk_dir(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.and_then(|e| Ok((e.path(), e.file_type()?))))
+        .collect::<Result<Vec<_>>>()?;
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    for (entry, file_type) in entries {
+        if file_type.is_dir() {
+            walk_dir(&entry, files)?;
+        } else if !(file_type.is_symlink() && entry.is_dir()) {
+            files.push(entry);
+        }
+    }
+
+    Ok(())
+}
+
+/// Expand the directories and glob patterns in a list of PCAPs.
+/// Directories are walked recursively and the files found are sorted by path. Files found
+/// while expanding are kept only if their extension matches `include` (when not empty) and does
+/// not match `exclude`.
+pub fn expand(pcaps: &[String], include: &[String], exclude: &[String]) -> Result<Vec<String>> {
+    let mut res = Vec::new();
+
+    for pcap in pcaps {
+        let path = Path::new(pcap);
+        let mut files = Vec::new();
+
+        if pcap == STDIN_PATH || path.is_file() {
+            // Explicitly listed files are always replayed.
+            res.push(pcap.clone());
+            continue;
+        } else if path.is_dir() {
+            walk_dir(path, &mut files)?;
+        } else if pcap.contains(['*', '?', '[']) {
+            let paths = glob::glob(pcap).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+            for path in paths {
+                let path = path.map_err(Error::from)?;
+                if path.is_dir() {
+                    walk_dir(&path, &mut files)?;
+                } else {
+                    files.push(path);
+                }
+            }
+
+            if files.is_empty() {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("No PCAP matches {}", pcap),
+                ));
+            }
+        } else {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("PCAP {} not found", pcap),
+            ));
+        }
+
+        res.extend(
+            files
+                .into_iter()
+                .filter(|f| include.is_empty() || has_extension(f, include))
+                .filter(|f| !has_extension(f, exclude))
+                .map(|f| f.to_string_lossy().into_owned()),
+        );
+    }
+
+    Ok(res)
+}
+
+/// Map a PCAP file in memory.
+pub fn map(path: &str) -> Result<Mmap> {
+    let file = File::open(path)?;
+
+    // Safety: the file is only read and it is expected not to be modified while replaying it.
+    unsafe { Mmap::map(&file) }
+}
+
+/// Open a PCAP file (or the standard input) for reading.
+pub fn open(path: &str) -> Result<Box<dyn Read>> {
+    if path == STDIN_PATH {
+        decompress(stdin())
+    } else {
+        decompress(File::open(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use crate::input::{decompress, expand};
+
+    /// Decompress `data` and return the result.
+    fn read_all(data: &[u8]) -> Vec<u8> {
+        let mut res = Vec::new();
+        decompress(data).unwrap().read_to_end(&mut res).unwrap();
+        res
+    }
+
+    #[test]
+    fn test_decompress() {
+        let data = b"\xd4\xc3\xb2\xa1 not really a pcap".to_vec();
+
+        // Plain.
+        assert_eq!(read_all(&data), data);
+
+        // Gzip.
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&data).unwrap();
+        assert_eq!(read_all(&encoder.finish().unwrap()), data);
+
+        // Xz.
+        let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+        encoder.write_all(&data).unwrap();
+        assert_eq!(read_all(&encoder.finish().unwrap()), data);
+
+        // Zstd.
+        assert_eq!(read_all(&zstd::encode_all(&data[..], 0).unwrap()), data);
+    }
+
+    #[test]
+    fn test_expand() {
+        let dir = std::env::temp_dir().join(format!("pcap_replay_expand_{}", std::process::id()));
+        for file in ["b.pcap", "a.pcapng", "sub/c.pcap.gz", "sub/d.txt"] {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::File::create(path).unwrap();
+        }
+        let dirs = [dir.to_string_lossy().into_owned()];
+        let path = |file: &str| dir.join(file).to_string_lossy().into_owned();
+
+        // Directories are walked recursively and sorted.
+        assert_eq!(
+            expand(&dirs, &[], &[]).unwrap(),
+            [
+                path("a.pcapng"),
+                path("b.pcap"),
+                path("sub/c.pcap.gz"),
+                path("sub/d.txt")
+            ]
+        );
+
+        // Extensions filters.
+        assert_eq!(
+            expand(
+                &dirs,
+                &["pcap".to_string(), "pcapng".to_string(), "gz".to_string()],
+                &["pcap.gz".to_string()]
+            )
+            .unwrap(),
+            [path("a.pcapng"), path("b.pcap")]
+        );
+
+        // Glob patterns, explicit files and stdin.
+        assert_eq!(
+            expand(
+                &[path("*.pcap*"), "-".to_string(), path("sub/d.txt")],
+                &["pcap".to_string()],
+                &[]
+            )
+            .unwrap(),
+            [path("b.pcap"), "-".to_string(), path("sub/d.txt")]
+        );
+
+        // Links to directories are not followed.
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&dir, dir.join("sub/loop")).unwrap();
+            assert_eq!(
+                expand(&dirs, &["gz".to_string()], &[]).unwrap(),
+                [path("sub/c.pcap.gz")]
+            );
+        }
+
+        // Missing files.
+        assert!(expand(&[path("missing.pcap")], &[], &[]).is_err());
+        assert!(expand(&[path("*.missing")], &[], &[]).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,299 @@
+//! Convert captured frames of any supported link type to Ethernet frames.
+
+use pcap_parser::Linktype;
+
+/// Linux "cooked" capture encapsulation v2 (missing from `pcap_parser`).
+const LINKTYPE_LINUX_SLL2: Linktype = Linktype(276);
+
+/// IPv4 ethertype.
+pub(crate) const ETHERTYPE_IPV4: u16 = 0x0800;
+/// ARP ethertype.
+pub(crate) const ETHERTYPE_ARP: u16 = 0x0806;
+/// IPv6 ethertype.
+pub(crate) const ETHERTYPE_IPV6: u16 = 0x86dd;
+/// VLAN ethertype (802.1Q).
+pub(crate) const ETHERTYPE_VLAN: u16 = 0x8100;
+/// QinQ ethertype (802.1ad).
+pub(crate) const ETHERTYPE_QINQ: u16 = 0x88a8;
+/// Smallest value of the SLL protocol field that is an actual ethertype.
+const ETHERTYPE_MIN: u16 = 0x0600;
+
+/// Length of the Ethernet header.
+const ETHERNET_HEADER_LEN: usize = 14;
+/// Length of the BSD loopback header.
+const NULL_HEADER_LEN: usize = 4;
+/// Length of the Linux "cooked" capture v1 header.
+const SLL_HEADER_LEN: usize = 16;
+/// Length of the Linux "cooked" capture v2 header.
+const SLL2_HEADER_LEN: usize = 20;
+
+/// MAC address.
+pub type MacAddr = [u8; 6];
+
+/// Version of an IP packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpVersion {
+    V4,
+    V6,
+}
+
+/// Parse a MAC address in the "aa:bb:cc:dd:ee:ff" format.
+pub fn parse_mac(s: &str) -> Result<MacAddr, String> {
+    let mut mac: MacAddr = [0; 6];
+    let mut octets = s.split(':');
+
+    for octet in mac.iter_mut() {
+        *octet = octets
+            .next()
+            .and_then(|o| u8::from_str_radix(o, 16).ok())
+            .ok_or_else(|| format!("Invalid MAC address: {}", s))?;
+    }
+
+    if octets.next().is_some() {
+        return Err(format!("Invalid MAC address: {}", s));
+    }
+
+    Ok(mac)
+}
+
+/// Return the ethertype of a raw IP packet, looking at its version.
+fn ip_ethertype(data: &[u8]) -> Option<u16> {
+    match data.first()? >> 4 {
+        4 => Some(ETHERTYPE_IPV4),
+        6 => Some(ETHERTYPE_IPV6),
+        _ => None,
+    }
+}
+
+/// Return the ethertype corresponding to a BSD address family.
+fn af_ethertype(af: u32) -> Option<u16> {
+    match af {
+        2 => Some(ETHERTYPE_IPV4),
+        // AF_INET6 differs among BSDs (NetBSD/OpenBSD, FreeBSD, Darwin).
+        24 | 28 | 30 => Some(ETHERTYPE_IPV6),
+        _ => None,
+    }
+}
+
+/// Read a big endian u16 at the given offset.
+pub(crate) fn read_u16_be(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().unwrap(),
+    ))
+}
+
+/// Skip the Ethernet header and the VLAN tags (802.1Q and 802.1ad) of a frame.
+/// Return the ethertype of the payload and its offset in the frame.
+pub(crate) fn parse_ethernet(frame: &[u8]) -> Option<(u16, usize)> {
+    let mut offset = ETHERNET_HEADER_LEN;
+    let mut ethertype = read_u16_be(frame, offset - 2)?;
+    while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+        offset += 4;
+        ethertype = read_u16_be(frame, offset - 2)?;
+    }
+
+    Some((ethertype, offset))
+}
+
+/// Strip the Ethernet header, the VLAN tags and the trailer (padding of the short frames) of a
+/// frame carrying an IP packet.
+/// Return the version of the packet and the packet itself. Packets whose length is unknown
+/// (e.g. truncated headers, or a zero length set by segmentation offloads) are not trimmed.
+pub fn strip_ethernet(frame: &[u8]) -> Option<(IpVersion, &[u8])> {
+    let (ethertype, offset) = parse_ethernet(frame)?;
+    let packet = &frame[offset..];
+    let (version, len) = match ethertype {
+        ETHERTYPE_IPV4 => (
+            IpVersion::V4,
+            read_u16_be(packet, 2)
+                .map(usize::from)
+                .filter(|len| *len >= 20),
+        ),
+        ETHERTYPE_IPV6 => (
+            IpVersion::V6,
+            read_u16_be(packet, 4)
+                .filter(|len| *len > 0)
+                .map(|len| usize::from(len) + 40),
+        ),
+        _ => return None,
+    };
+
+    Some((
+        version,
+        &packet[..len.map_or(packet.len(), |len| len.min(packet.len()))],
+    ))
+}
+
+/// Converter of captured frames to Ethernet frames.
+pub struct EthernetConverter {
+    /// Source MAC address of the synthesized Ethernet headers.
+    smac: MacAddr,
+    /// Destination MAC address of the synthesized Ethernet headers.
+    dmac: MacAddr,
+    /// Buffer holding the last converted frame.
+    buffer: Vec<u8>,
+}
+
+impl EthernetConverter {
+    /// Construct a converter synthesizing Ethernet headers with the given addresses.
+    pub fn new(smac: MacAddr, dmac: MacAddr) -> EthernetConverter {
+        EthernetConverter {
+            smac,
+            dmac,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Check whether frames of the given link type can be converted.
+    pub fn supports(linktype: Linktype) -> bool {
+        matches!(
+            linktype,
+            Linktype::ETHERNET
+                | Linktype::RAW
+                | Linktype::IPV4
+                | Linktype::IPV6
+                | Linktype::NULL
+                | Linktype::LOOP
+                | Linktype::LINUX_SLL
+                | LINKTYPE_LINUX_SLL2
+        )
+    }
+
+    /// Convert a frame to an Ethernet frame.
+    /// Return None if the frame cannot be converted (e.g. unknown network protocol).
+    pub fn convert<'a>(&'a mut self, linktype: Linktype, data: &'a [u8]) -> Option<&'a [u8]> {
+        let (ethertype, payload) = match linktype {
+            Linktype::ETHERNET => return Some(data),
+            Linktype::RAW | Linktype::IPV4 | Linktype::IPV6 => (ip_ethertype(data)?, data),
+            Linktype::NULL => {
+                // The address family is in the byte order of the capturing host.
+                let af = u32::from_le_bytes(data.get(..NULL_HEADER_LEN)?.try_into().unwrap());
+                let af = if af > 0xffff { af.swap_bytes() } else { af };
+                (af_ethertype(af)?, &data[NULL_HEADER_LEN..])
+            }
+            Linktype::LOOP => {
+                let af = u32::from_be_bytes(data.get(..NULL_HEADER_LEN)?.try_into().unwrap());
+                (af_ethertype(af)?, &data[NULL_HEADER_LEN..])
+            }
+            Linktype::LINUX_SLL => (
+                read_u16_be(data, SLL_HEADER_LEN - 2).filter(|e| *e >= ETHERTYPE_MIN)?,
+                data.get(SLL_HEADER_LEN..)?,
+            ),
+            LINKTYPE_LINUX_SLL2 => (
+                read_u16_be(data, 0).filter(|e| *e >= ETHERTYPE_MIN)?,
+                data.get(SLL2_HEADER_LEN..)?,
+            ),
+            _ => return None,
+        };
+
+        self.buffer.clear();
+        self.buffer.extend_from_slice(&self.dmac);
+        self.buffer.extend_from_slice(&self.smac);
+        self.buffer.extend_from_slice(&ethertype.to_be_bytes());
+        self.buffer.extend_from_slice(payload);
+
+        Some(&self.buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pcap_parser::Linktype;
+
+    use crate::link::{
+        parse_mac, strip_ethernet, EthernetConverter, IpVersion, LINKTYPE_LINUX_SLL2,
+    };
+
+    const SMAC: [u8; 6] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+    const DMAC: [u8; 6] = [0xff; 6];
+    const IPV4: &[u8] = &[0x45, 0x00, 0x00, 0x14];
+    const IPV6: &[u8] = &[0x60, 0x00, 0x00, 0x00];
+
+    /// Expected Ethernet frame for the given ethertype and payload.
+    fn ethernet(ethertype: [u8; 2], payload: &[u8]) -> Vec<u8> {
+        [&DMAC[..], &SMAC[..], &ethertype[..], payload].concat()
+    }
+
+    #[test]
+    fn test_parse_mac() {
+        assert_eq!(parse_mac("00:11:22:33:44:55"), Ok(SMAC));
+        assert!(parse_mac("00:11:22:33:44").is_err());
+        assert!(parse_mac("00:11:22:33:44:55:66").is_err());
+        assert!(parse_mac("00:11:22:33:44:zz").is_err());
+    }
+
+    #[test]
+    fn test_convert() {
+        let mut converter = EthernetConverter::new(SMAC, DMAC);
+
+        // Ethernet frames are left untouched.
+        assert_eq!(converter.convert(Linktype::ETHERNET, IPV4), Some(IPV4));
+
+        // Raw IP.
+        assert_eq!(
+            converter.convert(Linktype::RAW, IPV4),
+            Some(&ethernet([0x08, 0x00], IPV4)[..])
+        );
+        assert_eq!(
+            converter.convert(Linktype::RAW, IPV6),
+            Some(&ethernet([0x86, 0xdd], IPV6)[..])
+        );
+        assert_eq!(converter.convert(Linktype::RAW, &[0x00]), None);
+
+        // BSD loopback, both byte orders.
+        let null = [&[2, 0, 0, 0][..], IPV4].concat();
+        assert_eq!(
+            converter.convert(Linktype::NULL, &null),
+            Some(&ethernet([0x08, 0x00], IPV4)[..])
+        );
+        let null = [&[0, 0, 0, 30][..], IPV6].concat();
+        assert_eq!(
+            converter.convert(Linktype::NULL, &null),
+            Some(&ethernet([0x86, 0xdd], IPV6)[..])
+        );
+        assert_eq!(
+            converter.convert(Linktype::LOOP, &null),
+            Some(&ethernet([0x86, 0xdd], IPV6)[..])
+        );
+
+        // Linux cooked captures.
+        let mut sll = vec![0; 16];
+        sll[14..].copy_from_slice(&[0x08, 0x00]);
+        sll.extend_from_slice(IPV4);
+        assert_eq!(
+            converter.convert(Linktype::LINUX_SLL, &sll),
+            Some(&ethernet([0x08, 0x00], IPV4)[..])
+        );
+        let mut sll2 = vec![0; 20];
+        sll2[..2].copy_from_slice(&[0x86, 0xdd]);
+        sll2.extend_from_slice(IPV6);
+        assert_eq!(
+            converter.convert(LINKTYPE_LINUX_SLL2, &sll2),
+            Some(&ethernet([0x86, 0xdd], IPV6)[..])
+        );
+        assert_eq!(converter.convert(LINKTYPE_LINUX_SLL2, &sll2[..10]), None);
+    }
+
+    #[test]
+    fn test_strip_ethernet() {
+        assert_eq!(
+            strip_ethernet(&ethernet([0x08, 0x00], IPV4)),
+            Some((IpVersion::V4, IPV4))
+        );
+        let tagged = ethernet(
+            [0x88, 0xa8],
+            &[&[0, 1, 0x81, 0x00, 0, 2, 0x86, 0xdd], IPV6].concat(),
+        );
+        assert_eq!(strip_ethernet(&tagged), Some((IpVersion::V6, IPV6)));
+        // Trailers.
+        let header = [&[0x45, 0, 0, 20], &[0; 16][..]].concat();
+        let padded = ethernet([0x08, 0x00], &[&header[..], &[0xee; 26]].concat());
+        assert_eq!(strip_ethernet(&padded), Some((IpVersion::V4, &header[..])));
+        let header = [&[0x60, 0, 0, 0, 0, 2], &[0; 34][..], &[1, 2]].concat();
+        let padded = ethernet([0x86, 0xdd], &[&header[..], &[0xee; 4]].concat());
+        assert_eq!(strip_ethernet(&padded), Some((IpVersion::V6, &header[..])));
+        // ARP.
+        assert_eq!(strip_ethernet(&ethernet([0x08, 0x06], &[0; 28])), None);
+        assert_eq!(strip_ethernet(&tagged[..16]), None);
+    }
+}
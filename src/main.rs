@@ -6,25 +6,178 @@ mod windows;
 #[cfg(not(windows))]
 mod unix;
 
+mod bpf;
+mod filter;
+mod fixlen;
+mod input;
+mod link;
+mod merge;
+mod preload;
+mod reader;
 mod replay;
+mod select;
+mod sink;
+mod split;
 mod util;
+mod writer;
 
-use clap::Parser;
+use clap::{CommandFactory, ErrorKind, Parser};
 
 /// Reimplementation of the popular tool "tcpreplay" (it may require administrator privileges).
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None, arg_required_else_help = true)]
 pub struct Args {
+    /// Send up to NUM packets at once when they are already due.
+    #[clap(conflicts_with = "oneatatime", default_value_t = 1, long, value_name = "NUM",
+           value_parser = clap::value_parser!(u16).range(1..=1024),
+           long_help = "Send up to NUM packets at once when they are already due (e.g. with \
+                        topspeed or when late), with a single system call on linux network \
+                        interfaces (sendmmsg)\n\t- it must be in the range:\n\t1 to \
+                        1024\n\t- prohibits these options:\n\toneatatime")]
+    pub batch: u16,
+
+    /// Split the packets between intf1 and intf2 as stored in a cache file.
+    #[clap(
+        conflicts_with = "split",
+        long,
+        requires = "intf2",
+        short,
+        value_name = "FILE",
+        long_help = "Split the packets between intf1 and intf2 as stored in a cache file \
+                        (see save-cache)\n\t- it must be used with the same PCAPs and \
+                        options used to create it\n\t- prohibits these options:\n\tsplit"
+    )]
+    pub cachefile: Option<String>,
+
+    /// Networks of the clients (comma separated), when splitting the packets by CIDR.
+    #[clap(long, required_if_eq("split", "cidr"), use_value_delimiter = true,
+           value_name = "NETS", value_parser = split::parse_cidr,
+           long_help = "Networks of the clients (comma separated, e.g. \
+                        \"10.0.0.0/8,2001:db8::/32\"), when splitting the packets by CIDR")]
+    pub cidr: Vec<split::Cidr>,
+
+    /// Replay the PCAPs in pairs, sending the packets of the first one out of intf1 and the
+    /// packets of the second one out of intf2.
+    #[clap(conflicts_with_all = &["cachefile", "merge", "split"], long, requires = "intf2",
+           short = 'D',
+           long_help = "Replay the PCAPs in pairs, sending the packets of the first one out of \
+                        intf1 and the packets of the second one out of intf2\n\t- the two \
+                        PCAPs of each pair are merged by timestamp\n\t- prohibits these \
+                        options:\n\tcachefile\n\tmerge\n\tsplit")]
+    pub dualfile: bool,
+
     /// Limit the number of seconds to send.
     #[clap(default_value_t = u64::MAX, hide_default_value = true, long, value_name = "NUM",
            long_help = "Limit the number of seconds to send\n\t- it must be in the range:\n\t\
                         greater than or equal to 1")]
     pub duration: u64,
 
-    /// Input network interface.
-    #[clap(long, required = true, short)]
+    /// Number of the last packet of each capture to replay.
+    #[clap(default_value_t = u64::MAX, hide_default_value = true, long, value_name = "NUM",
+           long_help = "Number of the last packet of each capture to replay\n\t- it must be in \
+                        the range:\n\tgreater than or equal to start-packet")]
+    pub end_packet: u64,
+
+    /// Capture timestamp from which packets are no longer replayed.
+    #[clap(long, value_name = "TIME", value_parser = select::parse_time,
+           long_help = "Capture timestamp from which packets are no longer replayed\n\t- \
+                        UNIX timestamp (e.g. \"1669804990.5\")\n\t- UTC date (e.g. \
+                        \"2022-11-30 10:43:10.5\")\n\t- seconds after the first packet of the \
+                        capture (e.g. \"+30\")")]
+    pub end_time: Option<select::TimeBound>,
+
+    /// Destination MAC address of the synthesized Ethernet headers.
+    #[clap(default_value = "ff:ff:ff:ff:ff:ff", long, value_name = "MAC",
+           value_parser = link::parse_mac,
+           long_help = "Destination MAC address of the Ethernet headers synthesized for \
+                        non-Ethernet link types (e.g. RAW, LINUX_SLL, NULL)")]
+    pub enet_dmac: link::MacAddr,
+
+    /// Source MAC address of the synthesized Ethernet headers.
+    #[clap(default_value = "00:00:00:00:00:00", long, value_name = "MAC",
+           value_parser = link::parse_mac,
+           long_help = "Source MAC address of the Ethernet headers synthesized for \
+                        non-Ethernet link types (e.g. RAW, LINUX_SLL, NULL)")]
+    pub enet_smac: link::MacAddr,
+
+    /// Skip the PCAPs found in directories/glob patterns with one of these extensions.
+    #[clap(
+        long,
+        value_name = "EXT",
+        use_value_delimiter = true,
+        long_help = "Skip the PCAPs found in directories/glob patterns with one of these \
+                        extensions (comma separated, e.g. \"txt,pcap.gz\")"
+    )]
+    pub exclude_ext: Vec<String>,
+
+    /// Replay only the packets matching a filter expression (e.g. "tcp port 443").
+    #[clap(long, value_name = "EXPR", value_parser = filter::Filter::compile,
+           long_help = "Replay only the packets matching a filter expression in the pcap-filter \
+                        syntax (e.g. \"tcp port 443 and host 10.0.0.5\")\n\t- it supports the \
+                        ether/ip/ip6/arp/tcp/udp/sctp/icmp/icmp6 protocols, host, net, port, \
+                        portrange, proto, vlan, less and greater\n\t- packets not matching it \
+                        are not counted as sent")]
+    pub filter: Option<filter::Filter>,
+
+    /// Policy for packets captured partially (caplen < origlen).
+    #[clap(
+        default_value = "none",
+        long,
+        value_enum,
+        value_name = "POLICY",
+        long_help = "Policy for packets captured partially (caplen < origlen)\n\t- none: \
+                        send them as captured\n\t- pad: pad them with zeros up to their \
+                        original length\n\t- trunc: fix the IP/UDP/TCP lengths and checksums \
+                        to match the captured length"
+    )]
+    pub fixlen: fixlen::FixLen,
+
+    /// Replay only the PCAPs found in directories/glob patterns with one of these extensions.
+    #[clap(
+        long,
+        value_name = "EXT",
+        use_value_delimiter = true,
+        long_help = "Replay only the PCAPs found in directories/glob patterns with one of \
+                        these extensions (comma separated, e.g. \"pcap,pcapng\")"
+    )]
+    pub include_ext: Vec<String>,
+
+    /// Primary output: network interface or sink (e.g. "file:out.pcap").
+    #[clap(long, required_unless_present_any = &["listnics", "write"], short,
+           value_name = "SINK",
+           long_help = "Primary output: network interface or sink\n\t- NAME: network \
+                        interface\n\t- file:PATH: PCAP/PCAPNG file (see write)\n\t- null: \
+                        discard the packets, printing the throughput of the replay engine\n\t\
+                        - tap:NAME: TAP device, created if it does not exist (linux)\n\t- \
+                        tun:NAME: TUN device, created if it does not exist, to which the IP \
+                        packets are written without their link layer header (linux)\n\t- \
+                        raw, raw:NAME: raw IP sockets (optionally bound to an interface), \
+                        through which the IP packets are routed to their destination (linux)\n\t\
+                        - xdp:NAME[,queue=N][,mode=copy|zerocopy]: AF_XDP socket bound to a \
+                        queue (0 by default) of a network interface, in zero-copy mode if its \
+                        driver supports it, copy mode otherwise (linux, packets up to 2 KB), \
+                        which keeps the packets preloaded (see preload-pcap) in its UMEM to \
+                        send them again without copy at each loop\n\t\
+                        - vxlan:ADDR[,vni=N], gre:ADDR, erspan:ADDR[,session=N], tzsp:ADDR: \
+                        UDP tunnel toward a remote collector (e.g. \"vxlan:10.0.0.9,vni=42\"), \
+                        whose port defaults to 4789 (VXLAN), 4754 (GRE in UDP and ERSPAN \
+                        type II) or 37008 (TZSP)\n\t- pcapip:ADDR, pcapip-listen:ADDR: \
+                        PCAP-over-IP stream to a TCP peer, connected to or accepted before \
+                        replaying (e.g. \"pcapip-listen::57012\"), whose port defaults to \
+                        57012")]
     pub intf1: Option<String>,
 
+    /// Secondary output, for the packets sent by the servers (same syntax as intf1).
+    #[clap(
+        long,
+        short = 'I',
+        value_name = "SINK",
+        long_help = "Secondary output, for the packets sent by the servers (same syntax as \
+                        intf1)\n\t- requires one of these options:\n\tcachefile\n\t\
+                        dualfile\n\tsplit"
+    )]
+    pub intf2: Option<String>,
+
     /// List the available network interfaces.
     #[clap(conflicts_with_all = &["intf1", "pcaps"], long)]
     pub listnics: bool,
@@ -57,10 +210,40 @@ pub struct Args {
     )]
     pub loopdelay_ms: u64,
 
+    /// Mark of the packets (linux network interfaces, raw and tunnel sockets).
+    #[clap(
+        long,
+        value_name = "NUM",
+        long_help = "Mark of the packets (linux network interfaces, raw and tunnel \
+                        sockets)\n\t- it sets SO_MARK on the sockets, to match the replayed \
+                        traffic in tc or nftables policies of the sending host\n\t- it \
+                        requires CAP_NET_ADMIN"
+    )]
+    pub mark: Option<u32>,
+
     /// Sleep for no more then X milliseconds between packets.
     #[clap(default_value_t = u64::MAX, hide_default_value = true, long, value_name = "NUM")]
     pub maxsleep: u64,
 
+    /// Read the PCAPs in place from memory mapped files.
+    #[clap(
+        conflicts_with = "preload-pcap",
+        long,
+        long_help = "Read the PCAPs in place from memory mapped files, without copying the \
+                        packets\n\t- compressed PCAPs and the standard input are read as \
+                        usual\n\t- prohibits these options:\n\tpreload-pcap"
+    )]
+    pub mmap: bool,
+
+    /// Merge the PCAPs by timestamp instead of replaying them sequentially.
+    #[clap(
+        long,
+        long_help = "Merge the PCAPs by timestamp instead of replaying them sequentially\n\t- \
+                        all the PCAPs are opened at once and their packets are replayed in \
+                        global timestamp order"
+    )]
+    pub merge: bool,
+
     /// Replay packets at a given Mbps.
     #[clap(conflicts_with_all = &["oneatatime", "pps", "topspeed", "x"], default_value_t = 0.0,
            hide_default_value = true, long, short = 'M', value_name = "STR",
@@ -74,7 +257,7 @@ pub struct Args {
                         options:\n\tmbps\n\tmultiplier\n\tpps\n\ttopspeed")]
     pub oneatatime: bool,
 
-    /// List of PCAPs to process.
+    /// List of PCAPs, directories or glob patterns to process ("-" reads from the standard input).
     #[clap(required = true)]
     pub pcaps: Vec<String>,
 
@@ -82,6 +265,16 @@ pub struct Args {
     #[clap(long, short = 'P')]
     pub pid: bool,
 
+    /// Preload packets into RAM before sending.
+    #[clap(
+        long,
+        short = 'K',
+        long_help = "Preload packets into RAM before sending\n\t- the PCAPs are parsed once and \
+                        replayed from memory at each loop\n\t- it fails if the PCAPs do not fit \
+                        in the available memory"
+    )]
+    pub preload_pcap: bool,
+
     /// Replay packets at a given packets/sec.
     #[clap(conflicts_with_all = &["mbps", "oneatatime", "topspeed", "x"], default_value_t = 0.0,
            hide_default_value = true, long, short, value_name = "STR",
@@ -89,12 +282,96 @@ pub struct Args {
                         multiplier\n\tmbps\n\toneatatime\n\ttopspeed")]
     pub pps: f64,
 
+    /// Priority of the packets (linux network interfaces, raw and tunnel sockets).
+    #[clap(
+        long,
+        value_name = "NUM",
+        long_help = "Priority of the packets (linux network interfaces, raw and tunnel \
+                        sockets)\n\t- it sets SO_PRIORITY on the sockets, which selects the \
+                        traffic class of the queueing discipline\n\t- the values above 6 \
+                        require CAP_NET_ADMIN"
+    )]
+    pub priority: Option<u32>,
+
+    /// Bypass the queueing discipline of the interfaces (linux network interfaces).
+    #[clap(
+        long,
+        long_help = "Bypass the queueing discipline of the interfaces (linux network \
+                        interfaces)\n\t- it sets PACKET_QDISC_BYPASS on the sockets, so that \
+                        the packets are handed to the driver directly and cannot be dropped by \
+                        the local queueing discipline\n\t- the packets are dropped instead \
+                        when the driver queue is full, and they are not seen by tc"
+    )]
+    pub qdisc_bypass: bool,
+
+    /// Save the split decisions in a cache file, to replay them with the option 'cachefile'.
+    #[clap(long, requires = "split", value_name = "FILE")]
+    pub save_cache: Option<String>,
+
+    /// Size of the send buffer of the sockets, in bytes (linux network interfaces, raw and
+    /// tunnel sockets).
+    #[clap(long, value_name = "NUM",
+           value_parser = clap::value_parser!(u32).range(1..=i32::MAX as i64),
+           long_help = "Size of the send buffer of the sockets, in bytes (linux network \
+                        interfaces, raw and tunnel sockets)\n\t- it sets SO_SNDBUF on the \
+                        sockets, beyond net.core.wmem_max with CAP_NET_ADMIN\n\t- the kernel \
+                        doubles the value, to account for its bookkeeping overhead")]
+    pub sndbuf: Option<u32>,
+
+    /// Split the packets between intf1 (clients) and intf2 (servers).
+    #[clap(
+        long,
+        requires = "intf2",
+        value_enum,
+        value_name = "MODE",
+        long_help = "Split the packets between intf1 (clients) and intf2 (servers)\n\t- \
+                        cidr: the packets sent by the networks in 'cidr' are client packets\n\t\
+                        - port: the packets sent to a lower port than their source port are \
+                        client packets\n\t- syn: the packets of the TCP connection initiators \
+                        (first SYN seen) are client packets, the other ones are split by port"
+    )]
+    pub split: Option<split::SplitMode>,
+
+    /// Number of the first packet of each capture to replay.
+    #[clap(default_value_t = 1, long, value_name = "NUM",
+           value_parser = clap::value_parser!(u64).range(1..),
+           long_help = "Number of the first packet of each capture to replay\n\t- it must be \
+                        in the range:\n\tgreater than or equal to 1")]
+    pub start_packet: u64,
+
+    /// Capture timestamp from which packets are replayed.
+    #[clap(long, value_name = "TIME", value_parser = select::parse_time,
+           long_help = "Capture timestamp from which packets are replayed\n\t- UNIX timestamp \
+                        (e.g. \"1669804990.5\")\n\t- UTC date (e.g. \"2022-11-30 \
+                        10:43:10.5\")\n\t- seconds after the first packet of the capture \
+                        (e.g. \"+30\")")]
+    pub start_time: Option<select::TimeBound>,
+
     /// Replay packets as fast as possible.
     #[clap(conflicts_with_all = &["mbps", "oneatatime", "pps", "x"], long, short,
            long_help = "Replay packets as fast as possible\n\t- prohibits these options:\n\t\
                         pps\n\tmbps\n\toneatatime\n\ttopspeed")]
     pub topspeed: bool,
 
+    /// Send the packets through a PACKET_MMAP TX ring (linux network interfaces).
+    #[clap(
+        long,
+        long_help = "Send the packets through a PACKET_MMAP TX ring (linux network \
+                        interfaces)\n\t- the packets are copied to a ring shared with the \
+                        kernel, which sends them at once (see batch)\n\t- it falls back to \
+                        write() when the ring cannot be set up, and for the packets larger than \
+                        its frames (2 KB)"
+    )]
+    pub tx_ring: bool,
+
+    /// Write the packets to a PCAP/PCAPNG file instead of sending them.
+    #[clap(conflicts_with_all = &["intf1", "intf2"], long, short, value_name = "FILE",
+           long_help = "Write the packets to a PCAP/PCAPNG file instead of sending them\n\t- \
+                        the packets are stamped with the time they would have been sent at\n\t\
+                        - the PCAPNG format is used for the \".pcapng\" extension, the PCAP \
+                        format otherwise\n\t- prohibits these options:\n\tintf1\n\tintf2")]
+    pub write: Option<String>,
+
     /// Modify replay speed to a given multiple.
     #[clap(conflicts_with_all = &["mbps", "oneatatime", "pps", "topspeed"], default_value_t = 1.0,
            long = "multiplier", short, value_name = "STR",
@@ -115,6 +392,35 @@ fn main() {
         return;
     }
 
+    if args.oneatatime && args.pcaps.iter().any(|pcap| pcap == input::STDIN_PATH) {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "The option 'oneatatime' cannot be used when reading PCAPs from the standard \
+                 input",
+            )
+            .exit();
+    }
+
+    if args.intf2.is_some() && args.split.is_none() && args.cachefile.is_none() && !args.dualfile {
+        Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "The option 'intf2' requires one of the options 'split', 'cachefile' or \
+                 'dualfile'",
+            )
+            .exit();
+    }
+
+    if args.end_packet < args.start_packet {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "The option 'end-packet' must be greater than or equal to 'start-packet'",
+            )
+            .exit();
+    }
+
     let mut replayer = replay::Replayer::from_args(args);
     replayer.replay();
 }
@@ -0,0 +1,137 @@
+//! Merge multiple sources of packets by timestamp.
+
+use std::{cmp::Reverse, collections::BinaryHeap, io::Result};
+
+use crate::reader::{Packet, PacketSource};
+
+/// Source of the packets of multiple sources, in global timestamp order.
+/// Packets with the same timestamp are returned in the order of their sources.
+pub struct MergedSource<'a> {
+    /// Merged sources.
+    sources: Vec<Box<dyn PacketSource + 'a>>,
+    /// Timestamp of the current packet of each pending source (min-heap).
+    heads: BinaryHeap<Reverse<(u64, usize)>>,
+    /// Index of the source holding the current packet.
+    current: Option<usize>,
+    /// Whether the sources have been read yet.
+    started: bool,
+}
+
+impl<'a> MergedSource<'a> {
+    /// Construct a source merging the given sources.
+    pub fn new(sources: Vec<Box<dyn PacketSource + 'a>>) -> MergedSource<'a> {
+        MergedSource {
+            heads: BinaryHeap::with_capacity(sources.len()),
+            sources,
+            current: None,
+            started: false,
+        }
+    }
+
+    /// Advance a source and track its next packet.
+    fn advance_source(&mut self, index: usize) -> Result<()> {
+        let source = &mut self.sources[index];
+        if source.advance()? {
+            let ts_ns = source.packet().map_or(0, |packet| packet.ts_ns);
+            self.heads.push(Reverse((ts_ns, index)));
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> PacketSource for MergedSource<'a> {
+    fn advance(&mut self) -> Result<bool> {
+        if !self.started {
+            self.started = true;
+            for index in 0..self.sources.len() {
+                self.advance_source(index)?;
+            }
+        } else if let Some(index) = self.current {
+            self.advance_source(index)?;
+        }
+
+        self.current = self.heads.pop().map(|Reverse((_, index))| index);
+
+        Ok(self.current.is_some())
+    }
+
+    fn packet(&self) -> Option<Packet<'_>> {
+        self.sources[self.current?].packet()
+    }
+
+    fn source_index(&self) -> usize {
+        self.current.unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Result;
+
+    use pcap_parser::Linktype;
+
+    use crate::{
+        merge::MergedSource,
+        reader::{Packet, PacketSource},
+    };
+
+    /// Source of packets with the given timestamps and data.
+    struct VecSource {
+        packets: Vec<(u64, u8)>,
+        current: Option<usize>,
+    }
+
+    impl PacketSource for VecSource {
+        fn advance(&mut self) -> Result<bool> {
+            let next = self.current.map_or(0, |index| index + 1);
+            self.current = Some(next);
+            Ok(next < self.packets.len())
+        }
+
+        fn packet(&self) -> Option<Packet<'_>> {
+            let (ts_ns, data) = self.packets.get(self.current?)?;
+            Some(Packet {
+                linktype: Linktype::ETHERNET,
+                data: std::slice::from_ref(data),
+                orig_len: 1,
+                ts_ns: *ts_ns,
+            })
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let source = |packets: Vec<(u64, u8)>| -> Box<dyn PacketSource> {
+            Box::new(VecSource {
+                packets,
+                current: None,
+            })
+        };
+        let mut merged = MergedSource::new(vec![
+            source(vec![(1, 1), (4, 4), (6, 6)]),
+            source(vec![]),
+            source(vec![(2, 2), (4, 5), (7, 7)]),
+            source(vec![(3, 3)]),
+        ]);
+
+        let mut res = Vec::new();
+        while merged.advance().unwrap() {
+            let packet = merged.packet().unwrap();
+            res.push((packet.ts_ns, packet.data[0], merged.source_index()));
+        }
+
+        assert_eq!(
+            res,
+            [
+                (1, 1, 0),
+                (2, 2, 2),
+                (3, 3, 3),
+                (4, 4, 0),
+                (4, 5, 2),
+                (6, 6, 0),
+                (7, 7, 2)
+            ]
+        );
+    }
+}
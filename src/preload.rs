@@ -0,0 +1,200 @@
+//! Preload PCAPs in memory.
+
+use std::io::{Error, ErrorKind, Result};
+
+use pcap_parser::Linktype;
+
+use crate::reader::{Packet, PacketSource};
+
+/// Largest growth of the arena at once, in bytes.
+const ARENA_CHUNK: usize = 16 << 20;
+/// Smallest growth of the arena at once, in bytes.
+const ARENA_MIN_CHUNK: usize = 4096;
+
+/// Metadata of a preloaded packet.
+struct PreloadedPacket {
+    /// Link type of the captured frame.
+    linktype: Linktype,
+    /// Offset of the frame in the arena.
+    offset: usize,
+    /// Length of the frame.
+    len: usize,
+    /// Original length of the frame.
+    orig_len: u32,
+    /// Capture timestamp in nanoseconds.
+    ts_ns: u64,
+}
+
+/// Packets of a PCAP preloaded in a single memory arena.
+pub struct PreloadedPcap {
+    /// Frames data, stored back to back.
+    arena: Vec<u8>,
+    /// Packets metadata.
+    packets: Vec<PreloadedPacket>,
+}
+
+impl PreloadedPcap {
+    /// Read all the packets of `source` in memory.
+    /// `budget` is the memory (in bytes) still available for preloading and it is decreased
+    /// by the memory used by the PCAP. The storage grows by bounded chunks, which are checked
+    /// against the budget before being allocated.
+    pub fn load(name: &str, source: &mut dyn PacketSource, budget: &mut u64) -> Result<Self> {
+        let mut pcap = PreloadedPcap {
+            arena: Vec::new(),
+            packets: Vec::new(),
+        };
+        let mut charge = |size: usize| {
+            if size as u64 > *budget {
+                return Err(Error::new(
+                    ErrorKind::OutOfMemory,
+                    format!("Not enough memory to preload {}", name),
+                ));
+            }
+            *budget -= size as u64;
+            Ok(())
+        };
+
+        while let Some(packet) = source.next_packet()? {
+            let arena = &mut pcap.arena;
+            if arena.capacity() - arena.len() < packet.data.len() {
+                let additional = packet
+                    .data
+                    .len()
+                    .max(arena.capacity().clamp(ARENA_MIN_CHUNK, ARENA_CHUNK));
+                charge(arena.len() + additional - arena.capacity())?;
+                arena.reserve_exact(additional);
+            }
+
+            let packets = &mut pcap.packets;
+            if packets.len() == packets.capacity() {
+                let size = std::mem::size_of::<PreloadedPacket>();
+                let additional =
+                    (packets.capacity() * size).clamp(ARENA_MIN_CHUNK, ARENA_CHUNK) / size;
+                charge(additional * size)?;
+                packets.reserve_exact(additional);
+            }
+
+            pcap.packets.push(PreloadedPacket {
+                linktype: packet.linktype,
+                offset: pcap.arena.len(),
+                len: packet.data.len(),
+                orig_len: packet.orig_len,
+                ts_ns: packet.ts_ns,
+            });
+            pcap.arena.extend_from_slice(packet.data);
+        }
+
+        // Give back the unused memory.
+        let capacity = pcap.size();
+        pcap.arena.shrink_to_fit();
+        pcap.packets.shrink_to_fit();
+        *budget += (capacity - pcap.size()) as u64;
+
+        Ok(pcap)
+    }
+
+    /// Memory allocated for the PCAP, in bytes.
+    fn size(&self) -> usize {
+        self.arena.capacity() + self.packets.capacity() * std::mem::size_of::<PreloadedPacket>()
+    }
+
+    /// Number of preloaded packets.
+    pub fn packet_cnt(&self) -> usize {
+        self.packets.len()
+    }
+
+    /// Return a source of the preloaded packets.
+    pub fn reader(&self) -> PreloadedPcapReader<'_> {
+        PreloadedPcapReader {
+            pcap: self,
+            current: None,
+        }
+    }
+}
+
+/// Source of the packets of a preloaded PCAP.
+pub struct PreloadedPcapReader<'a> {
+    /// Preloaded PCAP.
+    pcap: &'a PreloadedPcap,
+    /// Index of the current packet.
+    current: Option<usize>,
+}
+
+impl<'a> PacketSource for PreloadedPcapReader<'a> {
+    fn advance(&mut self) -> Result<bool> {
+        let next = self.current.map_or(0, |index| index + 1);
+        self.current = Some(next.min(self.pcap.packets.len()));
+
+        Ok(next < self.pcap.packets.len())
+    }
+
+    fn packet(&self) -> Option<Packet<'_>> {
+        let packet = self.pcap.packets.get(self.current?)?;
+
+        Some(Packet {
+            linktype: packet.linktype,
+            data: &self.pcap.arena[packet.offset..packet.offset + packet.len],
+            orig_len: packet.orig_len,
+            ts_ns: packet.ts_ns,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Result};
+
+    use pcap_parser::Linktype;
+
+    use crate::{
+        preload::PreloadedPcap,
+        reader::{Packet, PacketSource},
+    };
+
+    /// Source of `count` packets of `len` bytes.
+    struct RepeatSource {
+        data: Vec<u8>,
+        count: usize,
+        current: usize,
+    }
+
+    impl PacketSource for RepeatSource {
+        fn advance(&mut self) -> Result<bool> {
+            self.current += 1;
+            Ok(self.current <= self.count)
+        }
+
+        fn packet(&self) -> Option<Packet<'_>> {
+            Some(Packet {
+                linktype: Linktype::ETHERNET,
+                data: &self.data,
+                orig_len: self.data.len() as u32,
+                ts_ns: self.current as u64,
+            })
+        }
+    }
+
+    fn source(len: usize, count: usize) -> RepeatSource {
+        RepeatSource {
+            data: vec![0xab; len],
+            count,
+            current: 0,
+        }
+    }
+
+    #[test]
+    fn test_preload_budget() {
+        // The budget is charged for the memory actually allocated.
+        let mut budget = 1 << 30;
+        let pcap = PreloadedPcap::load("test", &mut source(1000, 10000), &mut budget).unwrap();
+        assert_eq!((1 << 30) - budget, pcap.size() as u64);
+        assert!(pcap.size() >= 1000 * 10000);
+        let mut reader = pcap.reader();
+        assert_eq!(reader.next_packet().unwrap().unwrap().data, [0xab; 1000]);
+
+        // The storage never grows beyond the budget.
+        let mut budget = 12 << 20;
+        let e = PreloadedPcap::load("test", &mut source(1000, 10000), &mut budget);
+        assert_eq!(e.err().unwrap().kind(), ErrorKind::OutOfMemory);
+    }
+}
@@ -0,0 +1,542 @@
+//! Read packets from PCAP/PCAPNG files.
+
+use std::{
+    io::{Error, ErrorKind, Read, Result},
+    ops::Range,
+};
+
+use memmap2::Mmap;
+use pcap_parser::{traits::PcapReaderIterator, *};
+
+use crate::{link::EthernetConverter, util::NS_PER_SEC};
+
+/// Initial size of the reading buffer.
+const BUFFER_SIZE: usize = 65536;
+
+/// A packet read from a PCAP/PCAPNG file.
+#[derive(Clone, Copy)]
+pub struct Packet<'a> {
+    /// Link type of the captured frame.
+    pub linktype: Linktype,
+    /// Captured frame.
+    pub data: &'a [u8],
+    /// Original length of the frame (it may be bigger than the captured data).
+    pub orig_len: u32,
+    /// Capture timestamp in nanoseconds.
+    pub ts_ns: u64,
+}
+
+/// Source of packets.
+pub trait PacketSource {
+    /// Move to the next packet. Return false when there are no more packets.
+    fn advance(&mut self) -> Result<bool>;
+
+    /// Return the current packet, if any.
+    fn packet(&self) -> Option<Packet<'_>>;
+
+    /// Return the index of the underlying source of the current packet, for sources merging
+    /// multiple ones.
+    fn source_index(&self) -> usize {
+        0
+    }
+
+    /// Read the next packet. Return None when there are no more packets.
+    fn next_packet(&mut self) -> Result<Option<Packet<'_>>> {
+        Ok(if self.advance()? { self.packet() } else { None })
+    }
+}
+
+/// Position and metadata of a packet within a buffer.
+#[derive(Clone)]
+struct PacketInfo {
+    /// Link type of the captured frame.
+    linktype: Linktype,
+    /// Range of the frame in the buffer.
+    range: Range<usize>,
+    /// Original length of the frame.
+    orig_len: u32,
+    /// Capture timestamp in nanoseconds.
+    ts_ns: u64,
+}
+
+impl PacketInfo {
+    /// Describe a packet whose data lies in the buffer starting at address `base`.
+    fn new(packet: &Packet, base: usize) -> PacketInfo {
+        let start = packet.data.as_ptr() as usize - base;
+
+        PacketInfo {
+            linktype: packet.linktype,
+            range: start..start + packet.data.len(),
+            orig_len: packet.orig_len,
+            ts_ns: packet.ts_ns,
+        }
+    }
+
+    /// Return the packet, given the buffer holding its data.
+    fn packet<'a>(&self, buffer: &'a [u8]) -> Packet<'a> {
+        Packet {
+            linktype: self.linktype,
+            data: &buffer[self.range.clone()],
+            orig_len: self.orig_len,
+            ts_ns: self.ts_ns,
+        }
+    }
+}
+
+/// Settings of an interface described in a PCAPNG section.
+struct NgInterface {
+    /// Link type.
+    linktype: Linktype,
+    /// Number of timestamp units per second.
+    ts_units: u64,
+    /// Timestamp offset in seconds (`if_tsoffset` option).
+    ts_offset: u64,
+}
+
+impl NgInterface {
+    /// Construct the settings of an interface given its timestamp resolution (`if_tsresol`
+    /// option): a negative power of 10, or of 2 if its most significant bit is set.
+    /// Return None if the resolution cannot be represented.
+    fn new(linktype: Linktype, ts_resol: u8, ts_offset: u64) -> Option<NgInterface> {
+        let exponent = u32::from(ts_resol & 0x7f);
+        let ts_units = match ts_resol & 0x80 {
+            0 => 10u64.checked_pow(exponent)?,
+            _ => 1u64.checked_shl(exponent)?,
+        };
+
+        Some(NgInterface {
+            linktype,
+            ts_units,
+            ts_offset,
+        })
+    }
+
+    /// Convert a packet timestamp to nanoseconds. Return None if it cannot be represented.
+    fn ts_ns(&self, ts_high: u32, ts_low: u32) -> Option<u64> {
+        let ts = u128::from(ts_high) << 32 | u128::from(ts_low);
+        let ts_ns = ts * u128::from(NS_PER_SEC) / u128::from(self.ts_units)
+            + u128::from(self.ts_offset) * u128::from(NS_PER_SEC);
+
+        u64::try_from(ts_ns).ok()
+    }
+}
+
+/// Check that frames of the given link type can be replayed.
+fn check_linktype(name: &str, linktype: Linktype) -> Result<()> {
+    if !EthernetConverter::supports(linktype) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Unsupported link type {:?} in {}", linktype, name),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Error for malformed PCAP/PCAPNG data.
+fn invalid_data(name: &str, e: impl std::fmt::Debug) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("Error while reading {}: {:?}", name, e),
+    )
+}
+
+/// Decoder of PCAP/PCAPNG blocks into packets, tracking the state of the capture.
+struct BlockDecoder {
+    /// Name of the PCAP (for error reporting).
+    name: String,
+    /// Whether the legacy PCAP timestamps have nanosecond precision.
+    nanosecond_precision: bool,
+    /// Link type of the legacy PCAP.
+    linktype: Linktype,
+    /// Interfaces described in the current PCAPNG section.
+    ng_interfaces: Vec<NgInterface>,
+    /// Timestamp of the last packet decoded.
+    last_ts_ns: u64,
+}
+
+impl BlockDecoder {
+    /// Construct a decoder for the given PCAP.
+    fn new(name: &str) -> BlockDecoder {
+        BlockDecoder {
+            name: name.to_string(),
+            nanosecond_precision: false,
+            linktype: Linktype::ETHERNET,
+            ng_interfaces: Vec::new(),
+            last_ts_ns: 0,
+        }
+    }
+
+    /// Decode a block. Return None if the block does not contain a packet.
+    fn decode<'b>(&mut self, block: &PcapBlockOwned<'b>) -> Result<Option<Packet<'b>>> {
+        let packet = match block {
+            PcapBlockOwned::LegacyHeader(header) => {
+                check_linktype(&self.name, header.network)?;
+                self.nanosecond_precision = header.is_nanosecond_precision();
+                self.linktype = header.network;
+                None
+            }
+            PcapBlockOwned::Legacy(b) => {
+                // The fractional part is either in microseconds or nanoseconds.
+                let ts_frac_ns = if self.nanosecond_precision {
+                    b.ts_usec as u64
+                } else {
+                    b.ts_usec as u64 * 1000
+                };
+                Some(Packet {
+                    linktype: self.linktype,
+                    data: b.data,
+                    orig_len: b.origlen,
+                    ts_ns: b.ts_sec as u64 * NS_PER_SEC + ts_frac_ns,
+                })
+            }
+            PcapBlockOwned::NG(Block::SectionHeader(_)) => {
+                // Interface IDs are scoped to their section.
+                self.ng_interfaces.clear();
+                None
+            }
+            PcapBlockOwned::NG(Block::InterfaceDescription(idb)) => {
+                check_linktype(&self.name, idb.linktype)?;
+                let ng_interface = NgInterface::new(idb.linktype, idb.if_tsresol, idb.if_tsoffset)
+                    .ok_or_else(|| {
+                        Error::new(
+                            ErrorKind::InvalidData,
+                            format!(
+                                "Invalid timestamp resolution {} in {}",
+                                idb.if_tsresol, self.name
+                            ),
+                        )
+                    })?;
+                self.ng_interfaces.push(ng_interface);
+                None
+            }
+            PcapBlockOwned::NG(Block::EnhancedPacket(epb)) => {
+                let ng_interface = self
+                    .ng_interfaces
+                    .get(epb.if_id as usize)
+                    .ok_or_else(|| invalid_data(&self.name, "unknown interface"))?;
+                // Strip the padding.
+                let caplen = epb.data.len().min(epb.caplen as usize);
+                Some(Packet {
+                    linktype: ng_interface.linktype,
+                    data: &epb.data[..caplen],
+                    orig_len: epb.origlen,
+                    ts_ns: ng_interface
+                        .ts_ns(epb.ts_high, epb.ts_low)
+                        .ok_or_else(|| invalid_data(&self.name, "timestamp out of range"))?,
+                })
+            }
+            PcapBlockOwned::NG(Block::SimplePacket(spb)) => {
+                // Simple Packet Blocks belong to the first interface and carry no timestamp:
+                // send them right after the previous packet.
+                let ng_interface = self
+                    .ng_interfaces
+                    .first()
+                    .ok_or_else(|| invalid_data(&self.name, "unknown interface"))?;
+                // Strip the padding.
+                let caplen = spb.data.len().min(spb.origlen as usize);
+                Some(Packet {
+                    linktype: ng_interface.linktype,
+                    data: &spb.data[..caplen],
+                    orig_len: spb.origlen,
+                    ts_ns: self.last_ts_ns,
+                })
+            }
+            _ => None,
+        };
+
+        if let Some(packet) = &packet {
+            self.last_ts_ns = packet.ts_ns;
+        }
+
+        Ok(packet)
+    }
+}
+
+/// Streaming reader of PCAP/PCAPNG files.
+pub struct PcapReader<'a> {
+    /// Name of the PCAP (for error reporting).
+    name: String,
+    /// Underlying PCAP/PCAPNG reader.
+    reader: Box<dyn PcapReaderIterator + 'a>,
+    /// Size of the reading buffer.
+    capacity: usize,
+    /// Size of the current block, to consume before reading the next one.
+    pending: usize,
+    /// Current packet, within the reader buffer.
+    current: Option<PacketInfo>,
+    /// Decoder of the blocks.
+    decoder: BlockDecoder,
+}
+
+impl<'a> PcapReader<'a> {
+    /// Construct a reader of the PCAP/PCAPNG data read from `input`.
+    pub fn new<R: Read + 'a>(name: &str, input: R) -> Result<PcapReader<'a>> {
+        let reader = create_reader(BUFFER_SIZE, input).map_err(|e| invalid_data(name, e))?;
+
+        Ok(PcapReader {
+            name: name.to_string(),
+            reader,
+            capacity: BUFFER_SIZE,
+            pending: 0,
+            current: None,
+            decoder: BlockDecoder::new(name),
+        })
+    }
+
+    /// Read more data, growing the buffer if it cannot hold the next block.
+    fn refill(&mut self) -> Result<()> {
+        if self.reader.reader_exhausted() {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("Truncated block in {}", self.name),
+            ));
+        }
+
+        if self.reader.data().len() == self.capacity {
+            self.capacity *= 2;
+            self.reader.grow(self.capacity);
+        }
+
+        self.reader.refill().map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("Failed to read {}: {:?}", self.name, e),
+            )
+        })
+    }
+}
+
+impl<'a> PacketSource for PcapReader<'a> {
+    fn advance(&mut self) -> Result<bool> {
+        // Consume the current packet.
+        self.reader.consume(self.pending);
+        self.pending = 0;
+        self.current = None;
+
+        loop {
+            let base = self.reader.data().as_ptr() as usize;
+
+            match self.reader.next() {
+                Ok((offset, block)) => {
+                    if let Some(packet) = self.decoder.decode(&block)? {
+                        // The packet data lives in the reader buffer until the block is
+                        // consumed.
+                        self.current = Some(PacketInfo::new(&packet, base));
+                        self.pending = offset;
+                        return Ok(true);
+                    }
+
+                    self.reader.consume(offset);
+                }
+                Err(PcapError::Eof) => return Ok(false),
+                Err(PcapError::Incomplete) => self.refill()?,
+                Err(e) => return Err(invalid_data(&self.name, e)),
+            }
+        }
+    }
+
+    fn packet(&self) -> Option<Packet<'_>> {
+        self.current
+            .as_ref()
+            .map(|info| info.packet(self.reader.data()))
+    }
+}
+
+/// Format of a memory mapped capture.
+enum MmapFormat {
+    /// Legacy PCAP, with the given endianness.
+    Legacy { big_endian: bool },
+    /// PCAPNG, with the endianness of the current section.
+    Ng { big_endian: bool },
+}
+
+/// Reader of memory mapped PCAP/PCAPNG files.
+/// Packets are read in place from the mapped file, without copying them.
+pub struct MmapPcapReader {
+    /// Name of the PCAP (for error reporting).
+    name: String,
+    /// Mapped file.
+    mmap: Mmap,
+    /// Offset of the next block.
+    offset: usize,
+    /// Format of the capture.
+    format: MmapFormat,
+    /// Current packet, within the mapped file.
+    current: Option<PacketInfo>,
+    /// Decoder of the blocks.
+    decoder: BlockDecoder,
+}
+
+impl MmapPcapReader {
+    /// Construct a reader of the PCAP/PCAPNG data of a mapped file.
+    pub fn new(name: &str, mmap: Mmap) -> Result<MmapPcapReader> {
+        let mut decoder = BlockDecoder::new(name);
+
+        let (offset, format) = if parse_sectionheaderblock(&mmap).is_ok() {
+            (0, MmapFormat::Ng { big_endian: false })
+        } else {
+            let (rem, header) = parse_pcap_header(&mmap).map_err(|e| invalid_data(name, e))?;
+            let big_endian = header.is_bigendian();
+            decoder.decode(&PcapBlockOwned::LegacyHeader(header))?;
+            (mmap.len() - rem.len(), MmapFormat::Legacy { big_endian })
+        };
+
+        Ok(MmapPcapReader {
+            name: name.to_string(),
+            mmap,
+            offset,
+            format,
+            current: None,
+            decoder,
+        })
+    }
+}
+
+impl PacketSource for MmapPcapReader {
+    fn advance(&mut self) -> Result<bool> {
+        self.current = None;
+
+        loop {
+            let data = &self.mmap[self.offset..];
+            if data.is_empty() {
+                return Ok(false);
+            }
+
+            let res = match &mut self.format {
+                MmapFormat::Legacy { big_endian: false } => {
+                    parse_pcap_frame(data).map(|(rem, b)| (rem, PcapBlockOwned::Legacy(b)))
+                }
+                MmapFormat::Legacy { big_endian: true } => {
+                    parse_pcap_frame_be(data).map(|(rem, b)| (rem, PcapBlockOwned::Legacy(b)))
+                }
+                MmapFormat::Ng { big_endian } => {
+                    let parse = if *big_endian {
+                        parse_block_be
+                    } else {
+                        parse_block_le
+                    };
+                    parse(data).map(|(rem, b)| {
+                        if let Block::SectionHeader(shb) = &b {
+                            *big_endian = shb.big_endian();
+                        }
+                        (rem, PcapBlockOwned::NG(b))
+                    })
+                }
+            };
+
+            match res {
+                Ok((rem, block)) => {
+                    self.offset += data.len() - rem.len();
+                    if let Some(packet) = self.decoder.decode(&block)? {
+                        self.current = Some(PacketInfo::new(&packet, self.mmap.as_ptr() as usize));
+                        return Ok(true);
+                    }
+                }
+                Err(nom::Err::Incomplete(_)) => {
+                    return Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        format!("Truncated block in {}", self.name),
+                    ))
+                }
+                Err(e) => return Err(invalid_data(&self.name, e)),
+            }
+        }
+    }
+
+    fn packet(&self) -> Option<Packet<'_>> {
+        self.current.as_ref().map(|info| info.packet(&self.mmap))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pcap_parser::Linktype;
+
+    use std::io::ErrorKind;
+
+    use crate::reader::{NgInterface, PacketSource, PcapReader};
+
+    /// Build a PCAPNG with an Ethernet interface of the given timestamp resolution and a packet
+    /// of the given timestamp.
+    fn pcapng(ts_resol: u8, ts: u64) -> Vec<u8> {
+        let block = |block_type: u32, body: &[u8]| {
+            let len = (12 + body.len()) as u32;
+            let mut block = [&block_type.to_le_bytes()[..], &len.to_le_bytes()[..], body].concat();
+            block.extend_from_slice(&len.to_le_bytes());
+            block
+        };
+
+        let shb = [&0x1a2b3c4du32.to_le_bytes()[..], &[1, 0, 0, 0], &[0xff; 8]].concat();
+        let idb = [
+            &[1, 0, 0, 0, 0, 0, 4, 0][..],
+            &[9, 0, 1, 0, ts_resol, 0, 0, 0],
+            &[0; 4],
+        ]
+        .concat();
+        let epb = [
+            &[0; 4][..],
+            &((ts >> 32) as u32).to_le_bytes(),
+            &(ts as u32).to_le_bytes(),
+            &60u32.to_le_bytes(),
+            &60u32.to_le_bytes(),
+            &[0; 60],
+        ]
+        .concat();
+
+        [block(0x0a0d0d0a, &shb), block(1, &idb), block(6, &epb)].concat()
+    }
+
+    #[test]
+    fn test_ng_interface_ts_ns() {
+        // Default resolution (microseconds).
+        let ng_interface = NgInterface::new(Linktype::ETHERNET, 6, 0).unwrap();
+        assert_eq!(ng_interface.ts_ns(0, 1500000), Some(1500000000));
+
+        // Nanoseconds resolution with offset.
+        let ng_interface = NgInterface::new(Linktype::ETHERNET, 9, 10).unwrap();
+        assert_eq!(ng_interface.ts_ns(0, 1500000), Some(10001500000));
+
+        // Power of two resolution (1/1024 s).
+        let ng_interface = NgInterface::new(Linktype::ETHERNET, 0x80 | 10, 0).unwrap();
+        assert_eq!(ng_interface.ts_ns(0, 1536), Some(1500000000));
+
+        // Picoseconds resolution, beyond 32 bits of fraction.
+        let ng_interface = NgInterface::new(Linktype::ETHERNET, 12, 0).unwrap();
+        let ts: u64 = 10_000_123_456_789_012;
+        assert_eq!(
+            ng_interface.ts_ns((ts >> 32) as u32, ts as u32),
+            Some(10_000_123_456_789)
+        );
+
+        // Timestamps beyond the range of the nanoseconds.
+        let ng_interface = NgInterface::new(Linktype::ETHERNET, 0, 0).unwrap();
+        assert_eq!(ng_interface.ts_ns(u32::MAX, u32::MAX), None);
+        let ng_interface = NgInterface::new(Linktype::ETHERNET, 9, u64::MAX).unwrap();
+        assert_eq!(ng_interface.ts_ns(0, 0), None);
+
+        // Resolutions which cannot be represented.
+        assert!(NgInterface::new(Linktype::ETHERNET, 19, 0).is_some());
+        assert!(NgInterface::new(Linktype::ETHERNET, 20, 0).is_none());
+        assert!(NgInterface::new(Linktype::ETHERNET, 0x80 | 63, 0).is_some());
+        assert!(NgInterface::new(Linktype::ETHERNET, 0x80 | 64, 0).is_none());
+        assert!(NgInterface::new(Linktype::ETHERNET, 0xff, 0).is_none());
+    }
+
+    #[test]
+    fn test_pcapng_ts_resol() {
+        // Picoseconds.
+        let data = pcapng(12, 1_500_000_000_000);
+        let mut reader = PcapReader::new("test.pcapng", &data[..]).unwrap();
+        assert_eq!(reader.next_packet().unwrap().unwrap().ts_ns, 1_500_000_000);
+
+        for ts_resol in [20, 0x80 | 64] {
+            let data = pcapng(ts_resol, 0);
+            let res = PcapReader::new("test.pcapng", &data[..]).and_then(|mut reader| {
+                reader.next_packet()?;
+                Ok(())
+            });
+            assert_eq!(res.unwrap_err().kind(), ErrorKind::InvalidData);
+        }
+    }
+}
@@ -3,19 +3,22 @@
 use super::Args;
 
 use std::{
-    fs::File,
-    time::{SystemTime, UNIX_EPOCH},
+    io::{Cursor, ErrorKind, Read, Result},
+    rc::Rc,
 };
 
-use pcap_parser::*;
-
-use crate::util;
-
-#[cfg(windows)]
-use crate::windows::interface::Interface;
-
-#[cfg(not(windows))]
-use crate::unix::interface::Interface;
+use crate::{
+    fixlen::LengthFixer,
+    input,
+    link::EthernetConverter,
+    merge::MergedSource,
+    preload::PreloadedPcap,
+    reader::{MmapPcapReader, PacketSource, PcapReader},
+    select::{PacketSelector, Selection},
+    sink::{self, FileSink, PacketSink, SinkOptions},
+    split::{Side, Splitter},
+    util::{self, now_ns, NS_PER_SEC},
+};
 
 /// Replayer object in charge of the main processing logic.
 pub struct Replayer {
@@ -25,14 +28,28 @@ pub struct Replayer {
     abort: bool,
     /// Sent bytes counter.
     byte_cnt: u64,
+    /// Failed packets counter.
+    packet_failed_cnt: u64,
+    /// Counter of packets captured partially.
+    packet_snapped_cnt: u64,
     /// Number of remaining packets to send when replaying in `oneatatime` mode.
     oneatatime_packets_cnt: u32,
     /// Sent packets counter.
     packet_cnt: u64,
+    /// Content of the packets waiting to be sent in a batch.
+    batch: Vec<u8>,
+    /// End offset of each packet of the batch in `batch`.
+    batch_ends: Vec<usize>,
+    /// Index of the sink of the batch.
+    batch_index: usize,
     /// Sent truncated packets counter.
     packet_truncated_cnt: u64,
+    /// Splitter of the packets between intf1 and intf2.
+    splitter: Option<Splitter>,
     /// Timestamp of the first packet sent.
-    start_ts_us: u64,
+    start_ts_ns: u64,
+    /// Content of the standard input, when it needs to be replayed more than once.
+    stdin_buffer: Option<Rc<[u8]>>,
 }
 
 impl Replayer {
@@ -42,31 +59,38 @@ impl Replayer {
             args,
             abort: false,
             byte_cnt: 0,
+            packet_failed_cnt: 0,
+            packet_snapped_cnt: 0,
             oneatatime_packets_cnt: 0,
             packet_cnt: 0,
+            batch: Vec::new(),
+            batch_ends: Vec::new(),
+            batch_index: 0,
             packet_truncated_cnt: 0,
-            start_ts_us: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_micros() as u64,
+            splitter: None,
+            start_ts_ns: now_ns(),
+            stdin_buffer: None,
         }
     }
 
-    /// Wait before sending the packet (if required).
-    fn _wait(&mut self, last_pkt_ts_us: u64, curr_pkt_ts_us: u64, elapsed_ts_us: u64) {
-        if last_pkt_ts_us == 0 {
+    /// Compute how long to wait before sending the packet, in nanoseconds.
+    /// The packets of the pending batch are considered as sent.
+    fn _wait_time(&mut self, last_pkt_ts_ns: u64, curr_pkt_ts_ns: u64, elapsed_ts_ns: u64) -> u64 {
+        if last_pkt_ts_ns == 0 {
             // First packet, don't need to wait.
-            return;
+            return 0;
         }
+        let packet_cnt = self.packet_cnt + self.batch_ends.len() as u64;
+        let byte_cnt = self.byte_cnt + self.batch.len() as u64;
 
         // Calculate how long to sleep.
-        let mut sleep_us: u64 = 0;
+        let mut sleep_ns: u64 = 0;
         match &self.args {
             Args { mbps, .. } if *mbps > 0.0 => {
-                let delta_us =
-                    (self.byte_cnt as f64 * 8.0 * 1000000.0 / (self.args.mbps * 1000000.0)) as u64;
-                if delta_us > elapsed_ts_us {
-                    sleep_us = delta_us - elapsed_ts_us;
+                let delta_ns = (byte_cnt as f64 * 8.0 * NS_PER_SEC as f64
+                    / (self.args.mbps * 1000000.0)) as u64;
+                if delta_ns > elapsed_ts_ns {
+                    sleep_ns = delta_ns - elapsed_ts_ns;
                 }
             }
             Args {
@@ -76,8 +100,12 @@ impl Replayer {
                 if self.oneatatime_packets_cnt == 0 {
                     println!(
                         "**** Next packet #{} out {}. How many packets do you wish to send?",
-                        self.packet_cnt + 1,
-                        self.args.intf1.as_ref().unwrap()
+                        packet_cnt + 1,
+                        self.args
+                            .write
+                            .as_ref()
+                            .or(self.args.intf1.as_ref())
+                            .unwrap()
                     );
                     let mut input = String::new();
                     self.oneatatime_packets_cnt = std::io::stdin()
@@ -86,117 +114,470 @@ impl Replayer {
                 }
                 println!(
                     "Sending packet {} out: {}",
-                    self.packet_cnt + 1,
-                    self.args.intf1.as_ref().unwrap()
+                    packet_cnt + 1,
+                    self.args
+                        .write
+                        .as_ref()
+                        .or(self.args.intf1.as_ref())
+                        .unwrap()
                 );
 
                 self.oneatatime_packets_cnt -= 1;
             }
             Args { pps, .. } if *pps > 0.0 => {
-                let delta_us = (self.packet_cnt as f64 * 1000000.0 / self.args.pps) as u64;
-                if delta_us > elapsed_ts_us {
-                    sleep_us = delta_us - elapsed_ts_us;
+                let delta_ns = (packet_cnt as f64 * NS_PER_SEC as f64 / self.args.pps) as u64;
+                if delta_ns > elapsed_ts_ns {
+                    sleep_ns = delta_ns - elapsed_ts_ns;
                 }
             }
             Args { topspeed: true, .. } => {}
             _ => {
                 // Rely on packets timestamps.
-                sleep_us = ((curr_pkt_ts_us - last_pkt_ts_us) as f64 * self.args.x) as u64;
+                // Out of order packets are sent right away.
+                sleep_ns =
+                    (curr_pkt_ts_ns.saturating_sub(last_pkt_ts_ns) as f64 * self.args.x) as u64;
             }
         }
 
-        // Adjust the sleep time to not exceed `maxsleep` and sleep.
-        if u128::from(sleep_us) > u128::from(self.args.maxsleep) * 1000 {
-            sleep_us = (u128::from(self.args.maxsleep) * 1000) as u64;
+        // Adjust the sleep time to not exceed `maxsleep`.
+        if u128::from(sleep_ns) > u128::from(self.args.maxsleep) * 1000000 {
+            sleep_ns = (u128::from(self.args.maxsleep) * 1000000) as u64;
+        }
+
+        sleep_ns
+    }
+
+    /// Queue a packet to the sink `index` of `sinks`, waiting before if required.
+    /// The packets are sent in batches of packets already due to the same sink.
+    /// Return false if the replay must be aborted.
+    fn _send_packet(
+        &mut self,
+        sinks: &mut [Box<dyn PacketSink>],
+        index: usize,
+        data: &[u8],
+        last_pkt_ts_ns: u64,
+        curr_pkt_ts_ns: u64,
+    ) -> bool {
+        // Check if we need to abort because of too many packets sent / too much time elapsed.
+        let elapsed_ts_ns = now_ns() - self.start_ts_ns;
+        if self.packet_cnt + self.batch_ends.len() as u64 >= self.args.limit
+            || u128::from(elapsed_ts_ns) >= u128::from(self.args.duration) * u128::from(NS_PER_SEC)
+        {
+            self._flush_batch(sinks);
+            self.abort = true;
+            return false;
+        }
+
+        // Send the pending batch before waiting, or if the packet goes to another sink.
+        let sleep_ns = self._wait_time(last_pkt_ts_ns, curr_pkt_ts_ns, elapsed_ts_ns);
+        if (sleep_ns > 0 || index != self.batch_index) && !self._flush_batch(sinks) {
+            return false;
         }
 
-        if sleep_us > 0 {
-            std::thread::sleep(std::time::Duration::from_micros(sleep_us));
+        // Wait, if required.
+        if sleep_ns > 0 {
+            std::thread::sleep(std::time::Duration::from_nanos(sleep_ns));
         }
+
+        // Send the packet right away, when not batching.
+        if self.args.batch == 1 {
+            let res = sinks[index].send(data);
+            return self._account_packet(res, data.len());
+        }
+
+        self.batch_index = index;
+        self.batch.extend_from_slice(data);
+        self.batch_ends.push(self.batch.len());
+        if self.batch_ends.len() >= usize::from(self.args.batch) {
+            return self._flush_batch(sinks);
+        }
+
+        true
     }
 
-    /// Replay a single PCAP file.
-    fn _replay_pcap(&mut self, interface: &mut Interface, pcap: &String) {
-        let file = File::open(pcap).unwrap();
-        let mut reader = create_reader(65536, file).unwrap();
-        let mut last_pkt_ts_us: u64 = 0;
+    /// Account for the result of sending a packet of `len` bytes.
+    /// Return false if the replay must be aborted.
+    fn _account_packet(&mut self, res: Result<usize>, len: usize) -> bool {
+        match res {
+            Ok(sent) => {
+                if sent < len {
+                    self.packet_truncated_cnt += 1;
+                } else {
+                    self.packet_cnt += 1;
+                }
+
+                self.byte_cnt += sent as u64;
+            }
+            // The sink cannot send this packet, but it can send the next ones.
+            Err(e) if e.kind() == ErrorKind::Unsupported => self.packet_failed_cnt += 1,
+            Err(e) => {
+                println!("{}", e);
+                self.abort = true;
+            }
+        }
+
+        !self.abort
+    }
+
+    /// Send the pending batch of packets.
+    /// Return false if the replay must be aborted.
+    fn _flush_batch(&mut self, sinks: &mut [Box<dyn PacketSink>]) -> bool {
+        if self.batch_ends.is_empty() {
+            return !self.abort;
+        }
+
+        let batch = std::mem::take(&mut self.batch);
+        let batch_ends = std::mem::take(&mut self.batch_ends);
+        let packets: Vec<&[u8]> = batch_ends
+            .iter()
+            .scan(0, |start, end| {
+                let packet = &batch[*start..*end];
+                *start = *end;
+                Some(packet)
+            })
+            .collect();
+        let sink = sinks[self.batch_index].as_mut();
+
+        let mut sent_cnt = 0;
+        while sent_cnt < packets.len() {
+            match sink.send_batch(&packets[sent_cnt..]) {
+                Ok(sent) => {
+                    for (len, data) in sent.iter().zip(&packets[sent_cnt..]) {
+                        self._account_packet(Ok(*len), data.len());
+                    }
+                    sent_cnt += sent.len();
+                }
+                Err(e) => {
+                    if !self._account_packet(Err(e), packets[sent_cnt].len()) {
+                        break;
+                    }
+                    sent_cnt += 1;
+                }
+            }
+        }
+
+        // Keep the buffers for the next batch.
+        drop(packets);
+        self.batch = batch;
+        self.batch.clear();
+        self.batch_ends = batch_ends;
+        self.batch_ends.clear();
+
+        !self.abort
+    }
+
+    /// Open a PCAP/PCAPNG file for reading.
+    fn _open_pcap(&self, pcap: &str) -> Result<Box<dyn PacketSource>> {
+        // Compressed files and the standard input cannot be read in place.
+        if self.args.mmap && pcap != input::STDIN_PATH {
+            let mmap = input::map(pcap)?;
+            if !input::is_compressed(&mmap) {
+                return Ok(Box::new(MmapPcapReader::new(pcap, mmap)?));
+            }
+        }
+
+        let input: Box<dyn Read> = match &self.stdin_buffer {
+            Some(buffer) if pcap == input::STDIN_PATH => Box::new(Cursor::new(buffer.clone())),
+            _ => input::open(pcap)?,
+        };
+
+        Ok(Box::new(PcapReader::new(pcap, input)?))
+    }
+
+    /// Record that the current packet is not sent, to keep the split decisions aligned with the
+    /// packets.
+    fn _skip_packet(&mut self) {
+        if let Some(splitter) = &mut self.splitter {
+            splitter.skip();
+        }
+    }
+
+    /// Replay the packets of a source.
+    /// `sinks` holds intf1 and, when splitting the packets, intf2.
+    fn _replay_source(&mut self, sinks: &mut [Box<dyn PacketSink>], source: &mut dyn PacketSource) {
+        let mut last_pkt_ts_ns: u64 = 0;
+        // Converter of the frames to Ethernet.
+        let mut converter = EthernetConverter::new(self.args.enet_smac, self.args.enet_dmac);
+        // Fixer of the packets captured partially.
+        let mut fixer = LengthFixer::new(self.args.fixlen);
+        // Selector of the packets to replay.
+        let mut selector = PacketSelector::new(
+            self.args.start_packet,
+            self.args.end_packet,
+            self.args.start_time,
+            self.args.end_time,
+        );
 
         loop {
-            match reader.next() {
-                Ok((offset, block)) => {
-                    match block {
-                        PcapBlockOwned::Legacy(b) => {
-                            // Check if we need to abort because of too many packets sent / too
-                            // much time elapsed.
-                            let curr_ts_us = SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap()
-                                .as_micros() as u64;
-                            let elapsed_ts_us = curr_ts_us - self.start_ts_us;
-                            if self.packet_cnt > self.args.limit
-                                || u128::from(elapsed_ts_us)
-                                    >= u128::from(self.args.duration) * 1000000
-                            {
+            let packet = match source.advance() {
+                Ok(true) => source.packet(),
+                Ok(false) => None,
+                Err(e) => {
+                    println!("{}", e);
+                    self.abort = true;
+                    return;
+                }
+            };
+            let packet = match packet {
+                Some(packet) => packet,
+                None => break,
+            };
+
+            // Skipped packets are not taken into account for the timing.
+            match selector.select(packet.ts_ns) {
+                Selection::Replay => {}
+                Selection::Skip => {
+                    self._skip_packet();
+                    continue;
+                }
+                Selection::Stop => break,
+            }
+
+            let missing = (packet.orig_len as usize).saturating_sub(packet.data.len());
+
+            match converter.convert(packet.linktype, packet.data) {
+                Some(frame) => {
+                    // Packets not matching the filter are skipped like unselected ones.
+                    let wire_len = frame.len() + missing;
+                    if let Some(filter) = &self.args.filter {
+                        if !filter.matches(frame, wire_len) {
+                            self._skip_packet();
+                            continue;
+                        }
+                    }
+
+                    if missing > 0 {
+                        self.packet_snapped_cnt += 1;
+                    }
+
+                    // In dualfile mode, the second PCAP of each pair goes out on intf2.
+                    let side = match &mut self.splitter {
+                        Some(splitter) => match splitter.split(frame) {
+                            Ok(side) => side,
+                            Err(e) => {
+                                println!("{}", e);
                                 self.abort = true;
                                 return;
                             }
+                        },
+                        None if self.args.dualfile && source.source_index() == 1 => Side::Server,
+                        None => Side::Client,
+                    };
+                    let index = match side {
+                        Side::Client => 0,
+                        Side::Server => 1,
+                    };
 
-                            // Wait, if required.
-                            let curr_pkt_ts_us: u64 =
-                                (b.ts_sec as u64 * 1000000) + b.ts_usec as u64;
-                            self._wait(last_pkt_ts_us, curr_pkt_ts_us, elapsed_ts_us);
-
-                            // Send data and update timestamp.
-                            match interface.inject_packet(b.data) {
-                                Err(e) => {
-                                    println!("{}", e);
-                                    self.abort = true;
-                                    return;
-                                }
-                                Ok(len) => {
-                                    if len < b.data.len() {
-                                        self.packet_truncated_cnt += 1;
-                                    } else {
-                                        self.packet_cnt += 1;
-                                    }
-
-                                    self.byte_cnt += len as u64;
-                                }
-                            }
-
-                            last_pkt_ts_us = curr_pkt_ts_us;
-                        }
-                        PcapBlockOwned::NG(_) => unreachable!(),
-                        _ => {}
+                    let frame = fixer.fix(frame, missing);
+                    if !self._send_packet(sinks, index, frame, last_pkt_ts_ns, packet.ts_ns) {
+                        return;
                     }
-                    reader.consume(offset);
                 }
-                Err(PcapError::Eof) => break,
-                Err(PcapError::Incomplete) => {
-                    reader.refill().unwrap();
+                // The frame cannot be converted to Ethernet.
+                None => {
+                    self.packet_failed_cnt += 1;
+                    self._skip_packet();
                 }
-                Err(e) => panic!("error while reading: {:?}", e),
+            }
+
+            last_pkt_ts_ns = packet.ts_ns;
+        }
+    }
+
+    /// Open the source of the packets of a PCAP/PCAPNG file, reading them from memory if they
+    /// have been preloaded.
+    fn _open_source<'a>(
+        &self,
+        pcap: &str,
+        index: usize,
+        preloaded: Option<&'a [PreloadedPcap]>,
+    ) -> Result<Box<dyn PacketSource + 'a>> {
+        match preloaded {
+            Some(preloaded) => Ok(Box::new(preloaded[index].reader())),
+            None => self._open_pcap(pcap),
+        }
+    }
+
+    /// Open the source of the packets of some PCAP/PCAPNG files merged by timestamp.
+    /// `first_index` is the index of the first file in the list of PCAPs.
+    fn _open_merged_source<'a>(
+        &self,
+        pcaps: &[String],
+        first_index: usize,
+        preloaded: Option<&'a [PreloadedPcap]>,
+    ) -> Result<Box<dyn PacketSource + 'a>> {
+        let sources = pcaps
+            .iter()
+            .enumerate()
+            .map(|(i, pcap)| self._open_source(pcap, first_index + i, preloaded))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Box::new(MergedSource::new(sources)))
+    }
+
+    /// Replay the packets of a source, if it was opened successfully.
+    fn _replay_opened_source(
+        &mut self,
+        sinks: &mut [Box<dyn PacketSink>],
+        source: Result<Box<dyn PacketSource + '_>>,
+    ) {
+        match source {
+            Ok(mut source) => {
+                self._replay_source(sinks, source.as_mut());
+                // Send the packets still pending, they are due.
+                self._flush_batch(sinks);
+            }
+            Err(e) => {
+                println!("{}", e);
+                self.abort = true;
             }
         }
     }
 
+    /// Preload the PCAP/PCAPNG files in memory.
+    /// `budget` is the memory (in bytes) still available for preloading.
+    fn _preload_pcaps(&self, pcaps: &[String], budget: &mut u64) -> Result<Vec<PreloadedPcap>> {
+        pcaps
+            .iter()
+            .map(|pcap| PreloadedPcap::load(pcap, self._open_pcap(pcap)?.as_mut(), budget))
+            .collect()
+    }
+
     /// Replay the pcap(s).
     pub fn replay(&mut self) {
-        // Validate the interface.
-        let mut interface = util::get_interface(self.args.intf1.as_ref().unwrap()).expect(
-            "Invalid intf1 specified. Please list \
-                                                         available interfaces with option \
-                                                         \"--listnics\"",
-        );
+        // Split the packets between the interfaces, if required.
+        self.splitter = match (&self.args.cachefile, self.args.split) {
+            (Some(cachefile), _) => match Splitter::load(cachefile) {
+                Ok(splitter) => Some(splitter),
+                Err(e) => {
+                    println!("Failed to load the cache file {}: {}", cachefile, e);
+                    return;
+                }
+            },
+            (None, Some(mode)) => Some(Splitter::new(mode, &self.args.cidr)),
+            (None, None) => None,
+        };
 
-        // Support only PCAP files in a first iteration.
-        for _ in 0..self.args.l {
-            for pcap in &self.args.pcaps.clone() {
-                self._replay_pcap(&mut interface, pcap);
+        // Expand directories and glob patterns.
+        let pcaps = match input::expand(
+            &self.args.pcaps,
+            &self.args.include_ext,
+            &self.args.exclude_ext,
+        ) {
+            Ok(pcaps) => pcaps,
+            Err(e) => {
+                println!("Failed to expand the list of PCAPs: {}", e);
+                return;
+            }
+        };
+        if self.args.dualfile && pcaps.len() % 2 != 0 {
+            println!("The option 'dualfile' requires an even number of PCAPs");
+            return;
+        }
 
-                // Check if need to abort because we reached some thresholds.
-                if self.abort {
-                    break;
+        // The standard input can be read only once: buffer it if it must be replayed more times.
+        let stdin_cnt = pcaps
+            .iter()
+            .filter(|pcap| *pcap == input::STDIN_PATH)
+            .count();
+        if stdin_cnt > 1 || (stdin_cnt == 1 && self.args.l > 1 && !self.args.preload_pcap) {
+            let mut buffer = Vec::new();
+            input::open(input::STDIN_PATH)
+                .and_then(|mut stdin| stdin.read_to_end(&mut buffer))
+                .expect("Failed to read the standard input");
+            self.stdin_buffer = Some(buffer.into());
+        }
+
+        // Preload the PCAPs, if required, keeping some memory for the rest of the system.
+        let mut budget = util::available_memory().map_or(u64::MAX, |memory| memory / 8 * 7);
+        let preloaded = if self.args.preload_pcap {
+            match self._preload_pcaps(&pcaps, &mut budget) {
+                Ok(preloaded) => Some(preloaded),
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            }
+        } else {
+            None
+        };
+
+        // Open the sinks.
+        let options = SinkOptions {
+            tx_ring: self.args.tx_ring,
+            qdisc_bypass: self.args.qdisc_bypass,
+            sndbuf: self.args.sndbuf,
+            priority: self.args.priority,
+            mark: self.args.mark,
+            preloaded: preloaded
+                .as_ref()
+                .map(|preloaded| preloaded.iter().map(PreloadedPcap::packet_cnt).sum()),
+        };
+        let sinks = match &self.args.write {
+            Some(path) => {
+                FileSink::create(path).map(|sink| vec![Box::new(sink) as Box<dyn PacketSink>])
+            }
+            None => self
+                .args
+                .intf1
+                .iter()
+                .chain(self.args.intf2.iter())
+                .map(|spec| sink::open(spec, &options, &mut budget))
+                .collect(),
+        };
+        let mut sinks = match sinks {
+            Ok(sinks) => sinks,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        for sink in &sinks {
+            for option in options.names() {
+                if !sink.supported_options().contains(&option) {
+                    println!(
+                        "The option --{} is not supported by {}, ignoring it.",
+                        option,
+                        sink.name()
+                    );
+                }
+            }
+        }
+        // The replay starts once the sinks are ready (e.g. once a client connected).
+        self.start_ts_ns = now_ns();
+
+        for l in 0..self.args.l {
+            if let Some(splitter) = &mut self.splitter {
+                splitter.rewind();
+            }
+            if l > 0 {
+                sinks.iter_mut().for_each(|sink| sink.rewind());
+            }
+
+            if self.args.dualfile {
+                // Replay each pair of PCAPs at once, in global timestamp order.
+                for (i, pair) in pcaps.chunks(2).enumerate() {
+                    let source = self._open_merged_source(pair, i * 2, preloaded.as_deref());
+                    self._replay_opened_source(&mut sinks, source);
+
+                    // Check if need to abort because we reached some thresholds.
+                    if self.abort {
+                        break;
+                    }
+                }
+            } else if self.args.merge {
+                // Replay all the PCAPs at once, in global timestamp order.
+                let source = self._open_merged_source(&pcaps, 0, preloaded.as_deref());
+                self._replay_opened_source(&mut sinks, source);
+            } else {
+                for (i, pcap) in pcaps.iter().enumerate() {
+                    let source = self._open_source(pcap, i, preloaded.as_deref());
+                    self._replay_opened_source(&mut sinks, source);
+
+                    // Check if need to abort because we reached some thresholds.
+                    if self.abort {
+                        break;
+                    }
                 }
             }
 
@@ -211,12 +592,22 @@ impl Replayer {
             }
         }
 
+        // Flush the packets still buffered by the sinks.
+        for sink in sinks.iter_mut() {
+            if let Err(e) = sink.flush() {
+                println!("{}", e);
+            }
+        }
+
+        // Save the split decisions, if required.
+        if let (Some(path), Some(splitter)) = (&self.args.save_cache, &self.splitter) {
+            if let Err(e) = splitter.save(path) {
+                println!("Failed to save the cache file {}: {}", path, e);
+            }
+        }
+
         // Print exit stats.
-        let end_ts_us = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_micros() as u64;
-        let elapsed_s = (end_ts_us - self.start_ts_us) as f64 / 1000000.0;
+        let elapsed_s = (now_ns() - self.start_ts_ns) as f64 / NS_PER_SEC as f64;
         println!(
             "Actual: {} packets ({} bytes) sent in {:.6} seconds",
             self.packet_cnt, self.byte_cnt, elapsed_s
@@ -227,13 +618,196 @@ impl Replayer {
             self.byte_cnt as f64 * 8.0 / 1000000.0 / elapsed_s,
             self.packet_cnt as f64 / elapsed_s
         );
+        match &self.args.write {
+            Some(path) => println!("Statistics for output file: {}", path),
+            None => println!("Statistics for output: {}", sinks[0].name()),
+        }
         println!(
-            "Statistics for network device: {}",
-            self.args.intf1.as_ref().unwrap()
-        );
-        println!(
-            "\tSuccessful packets:\t{}\n\tFailed packets:\t\t{}\n\tTruncated packets:\t{}",
-            self.packet_cnt, 0, self.packet_truncated_cnt
+            "\tSuccessful packets:\t{}\n\tFailed packets:\t\t{}\n\tTruncated packets:\t{}\n\t\
+             Partially captured:\t{}",
+            self.packet_cnt,
+            self.packet_failed_cnt,
+            self.packet_truncated_cnt,
+            self.packet_snapped_cnt
         );
+        for sink in &sinks {
+            let stats = sink.stats();
+            if sinks.len() > 1 {
+                println!("\tSent on {}:\t\t{}", sink.name(), stats.packets);
+            }
+            for (description, value) in stats.extra {
+                println!("\t{} ({}):\t{}", description, sink.name(), value);
+            }
+        }
+
+        // Print the throughput measured by the sinks.
+        for sink in &sinks {
+            let stats = sink.stats();
+            if let (Some(active_ns), true) = (stats.active_ns, stats.packets > 1) {
+                // The active time spans the intervals between the packets accepted.
+                let intervals = (stats.packets - 1) as f64;
+                let active_s = active_ns.max(1) as f64 / NS_PER_SEC as f64;
+                println!("Throughput of {}:", sink.name());
+                println!(
+                    "\tActive time:\t\t{:.6} seconds (first to last packet)",
+                    active_s
+                );
+                println!(
+                    "\tPackets:\t\t{:.2} pps\n\tBits:\t\t\t{:.3} Mbps",
+                    intervals / active_s,
+                    stats.bytes as f64 * 8.0 / 1000000.0 * intervals
+                        / stats.packets as f64
+                        / active_s
+                );
+                println!(
+                    "\tTime per packet:\t{:.1} ns\n\tAverage packet size:\t{:.1} bytes",
+                    active_ns as f64 / intervals,
+                    stats.bytes as f64 / stats.packets as f64
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Error, ErrorKind, Result};
+
+    use clap::Parser;
+
+    use crate::{
+        replay::Replayer,
+        sink::{PacketSink, SinkStats},
+        util::{now_ns, NS_PER_SEC},
+        Args,
+    };
+
+    /// Sink accepting at most `max_batch` packets per batch (as `sendmmsg` may), truncating
+    /// the packets to `max_len` bytes and rejecting the packets starting with 0xff.
+    struct TestSink {
+        max_batch: usize,
+        max_len: usize,
+        stats: SinkStats,
+    }
+
+    fn test_sink(max_batch: usize) -> Box<dyn PacketSink> {
+        Box::new(TestSink {
+            max_batch,
+            max_len: 100,
+            stats: SinkStats::default(),
+        })
+    }
+
+    impl PacketSink for TestSink {
+        fn name(&self) -> &str {
+            "test"
+        }
+
+        fn send(&mut self, data: &[u8]) -> Result<usize> {
+            if data[0] == 0xff {
+                return Err(Error::new(ErrorKind::Unsupported, "Rejected"));
+            }
+            let len = data.len().min(self.max_len);
+            self.stats.add(len);
+            Ok(len)
+        }
+
+        fn send_batch(&mut self, packets: &[&[u8]]) -> Result<Vec<usize>> {
+            let mut sent = Vec::new();
+            for data in packets.iter().take(self.max_batch) {
+                match self.send(data) {
+                    Ok(len) => sent.push(len),
+                    Err(e) if sent.is_empty() => return Err(e),
+                    Err(_) => break,
+                }
+            }
+            Ok(sent)
+        }
+
+        fn stats(&self) -> SinkStats {
+            self.stats.clone()
+        }
+    }
+
+    /// Replayer with the given extra arguments, replaying at top speed to a null sink.
+    fn replayer(args: &[&str]) -> Replayer {
+        let args = ["pcap_replay", "-i", "null", "-t"]
+            .iter()
+            .chain(args)
+            .chain(&["test.pcap"]);
+        Replayer::from_args(Args::parse_from(args))
+    }
+
+    /// Queue the packets to the first sink, then flush the batch.
+    fn send_all(replayer: &mut Replayer, sinks: &mut [Box<dyn PacketSink>], packets: &[Vec<u8>]) {
+        for (i, data) in packets.iter().enumerate() {
+            if !replayer._send_packet(sinks, 0, data, i as u64, i as u64 + 1) {
+                return;
+            }
+        }
+        replayer._flush_batch(sinks);
+    }
+
+    #[test]
+    fn test_batch_accounting() {
+        let packets = [
+            vec![0; 60],
+            vec![0; 150],
+            vec![0xff; 60],
+            vec![0; 60],
+            vec![0xff; 60],
+        ];
+
+        // Partial batches, truncated and rejected packets.
+        for batch in ["1", "2", "8"] {
+            let mut replayer = replayer(&["--batch", batch]);
+            let mut sinks = [test_sink(1)];
+            send_all(&mut replayer, &mut sinks, &packets);
+
+            assert!(!replayer.abort);
+            assert_eq!(replayer.packet_cnt, 2);
+            assert_eq!(replayer.packet_truncated_cnt, 1);
+            assert_eq!(replayer.packet_failed_cnt, 2);
+            assert_eq!(replayer.byte_cnt, 220);
+            assert!(replayer.batch_ends.is_empty());
+        }
+
+        // The whole batch is given to the sink, then what it did not send.
+        let mut replayer = replayer(&["--batch", "8"]);
+        let mut sinks = [test_sink(2)];
+        send_all(&mut replayer, &mut sinks, &packets);
+        assert_eq!(replayer.packet_cnt + replayer.packet_truncated_cnt, 3);
+        assert_eq!(replayer.packet_failed_cnt, 2);
+    }
+
+    #[test]
+    fn test_batch_flush_on_abort() {
+        let packets = vec![vec![0; 60]; 10];
+
+        // Exactly `limit` packets are sent, the pending ones being sent when the limit is
+        // reached, with batches smaller or larger than the limit.
+        for (batch, limit) in [("1", "3"), ("2", "5"), ("8", "3"), ("8", "8"), ("4", "9")] {
+            let mut replayer = replayer(&["--batch", batch, "--limit", limit]);
+            let mut sinks = [test_sink(usize::MAX)];
+            send_all(&mut replayer, &mut sinks, &packets);
+
+            assert!(replayer.abort);
+            assert!(replayer.batch_ends.is_empty());
+            assert_eq!(replayer.packet_cnt, limit.parse::<u64>().unwrap());
+            assert_eq!(sinks[0].stats().packets, replayer.packet_cnt);
+        }
+
+        // The pending packets are sent when the duration is reached.
+        let mut replayer = replayer(&["--batch", "8", "--duration", "1"]);
+        let mut sinks = [test_sink(usize::MAX)];
+        for data in &packets[..2] {
+            assert!(replayer._send_packet(&mut sinks, 0, data, 0, 0));
+        }
+        assert_eq!(replayer.batch_ends.len(), 2);
+        replayer.start_ts_ns = now_ns() - 2 * NS_PER_SEC;
+        assert!(!replayer._send_packet(&mut sinks, 0, &packets[2], 0, 0));
+        assert!(replayer.abort);
+        assert_eq!(replayer.packet_cnt, 2);
+        assert!(replayer.batch_ends.is_empty());
     }
 }
@@ -0,0 +1,222 @@
+//! Select the packets to replay within a capture, by packet number and capture timestamp.
+
+use crate::util::NS_PER_SEC;
+
+/// Bound of a time window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeBound {
+    /// UNIX timestamp in nanoseconds.
+    Absolute(u64),
+    /// Nanoseconds after the first packet of the capture.
+    Relative(u64),
+}
+
+/// Parse a number of seconds with an optional fractional part (e.g. "12.5") in nanoseconds.
+fn parse_seconds(s: &str) -> Option<u64> {
+    let (secs, frac) = s.split_once('.').unwrap_or((s, ""));
+    if secs.is_empty()
+        || frac.len() > 9
+        || !secs.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+
+    let frac_ns = format!("{:0<9}", frac).parse::<u64>().ok()?;
+    secs.parse::<u64>()
+        .ok()?
+        .checked_mul(NS_PER_SEC)?
+        .checked_add(frac_ns)
+}
+
+/// Number of days between the UNIX epoch and the given date of the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+/// Parse a UTC date in the "YYYY-MM-DD HH:MM:SS[.FRAC]" format in nanoseconds since the UNIX
+/// epoch.
+fn parse_date(s: &str) -> Option<u64> {
+    let (date, time) = s.split_once([' ', 'T'])?;
+
+    let mut fields = date.splitn(3, '-').map(|f| f.parse::<i64>().ok());
+    let (year, month, day) = (fields.next()??, fields.next()??, fields.next()??);
+
+    let mut fields = time.splitn(3, ':');
+    let hour = fields.next()?.parse::<u64>().ok()?;
+    let minute = fields.next()?.parse::<u64>().ok()?;
+    let second_ns = parse_seconds(fields.next()?)?;
+
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second_ns >= 61 * NS_PER_SEC
+    {
+        return None;
+    }
+
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    ((days * 24 + hour) * 60 + minute)
+        .checked_mul(60 * NS_PER_SEC)?
+        .checked_add(second_ns)
+}
+
+/// Parse a bound of a time window: a UNIX timestamp ("1669804990.5"), a UTC date
+/// ("2022-11-30 10:43:10.5") or a number of seconds after the first packet of the capture
+/// ("+30.5").
+pub fn parse_time(s: &str) -> Result<TimeBound, String> {
+    match s.strip_prefix('+') {
+        Some(offset) => parse_seconds(offset).map(TimeBound::Relative),
+        None => parse_seconds(s)
+            .or_else(|| parse_date(s))
+            .map(TimeBound::Absolute),
+    }
+    .ok_or_else(|| format!("Invalid time: {}", s))
+}
+
+/// Outcome of the selection of a packet.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Selection {
+    /// The packet must be replayed.
+    Replay,
+    /// The packet must be skipped.
+    Skip,
+    /// The packet and all the following ones must be skipped.
+    Stop,
+}
+
+/// Selector of the packets of a capture.
+pub struct PacketSelector {
+    /// Number of the first packet to replay (starting from 1).
+    start_packet: u64,
+    /// Number of the last packet to replay.
+    end_packet: u64,
+    /// Timestamp from which packets are replayed.
+    start_time: Option<TimeBound>,
+    /// Timestamp from which packets are no longer replayed.
+    end_time: Option<TimeBound>,
+    /// Number of packets of the capture seen so far.
+    packet_cnt: u64,
+    /// Timestamp of the first packet of the capture in nanoseconds.
+    first_ts_ns: u64,
+}
+
+impl PacketSelector {
+    /// Construct a selector of the packets numbered between `start_packet` and `end_packet`
+    /// (inclusive) and captured between `start_time` (inclusive) and `end_time` (exclusive).
+    pub fn new(
+        start_packet: u64,
+        end_packet: u64,
+        start_time: Option<TimeBound>,
+        end_time: Option<TimeBound>,
+    ) -> PacketSelector {
+        PacketSelector {
+            start_packet,
+            end_packet,
+            start_time,
+            end_time,
+            packet_cnt: 0,
+            first_ts_ns: 0,
+        }
+    }
+
+    /// Resolve a time bound to a timestamp in nanoseconds.
+    fn resolve(&self, bound: TimeBound) -> u64 {
+        match bound {
+            TimeBound::Absolute(ts_ns) => ts_ns,
+            TimeBound::Relative(offset_ns) => self.first_ts_ns.saturating_add(offset_ns),
+        }
+    }
+
+    /// Select the next packet of the capture, given its timestamp.
+    pub fn select(&mut self, ts_ns: u64) -> Selection {
+        self.packet_cnt += 1;
+        if self.packet_cnt == 1 {
+            self.first_ts_ns = ts_ns;
+        }
+
+        if self.packet_cnt > self.end_packet {
+            return Selection::Stop;
+        }
+
+        let after_start = self
+            .start_time
+            .map_or(true, |start| ts_ns >= self.resolve(start));
+        let before_end = self.end_time.map_or(true, |end| ts_ns < self.resolve(end));
+        if self.packet_cnt < self.start_packet || !after_start || !before_end {
+            return Selection::Skip;
+        }
+
+        Selection::Replay
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::select::{parse_time, PacketSelector, Selection, TimeBound};
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("+30"), Ok(TimeBound::Relative(30_000_000_000)));
+        assert_eq!(parse_time("+0.25"), Ok(TimeBound::Relative(250_000_000)));
+        assert_eq!(
+            parse_time("1669804990.000001"),
+            Ok(TimeBound::Absolute(1_669_804_990_000_001_000))
+        );
+        assert_eq!(
+            parse_time("2022-11-30 10:43:10.5"),
+            Ok(TimeBound::Absolute(1_669_804_990_500_000_000))
+        );
+        assert_eq!(
+            parse_time("1970-01-01T00:00:00"),
+            Ok(TimeBound::Absolute(0))
+        );
+        assert!(parse_time("").is_err());
+        assert!(parse_time("+").is_err());
+        assert!(parse_time("-5").is_err());
+        assert!(parse_time("1.0000000001").is_err());
+        assert!(parse_time("2022-13-30 10:43:10").is_err());
+        assert!(parse_time("2022-11-30 10:43").is_err());
+    }
+
+    #[test]
+    fn test_select() {
+        let select = |mut selector: PacketSelector| {
+            (1..=10)
+                .map(|ts_ns| selector.select(ts_ns * 100))
+                .collect::<Vec<_>>()
+        };
+        let replayed = |selection: &[Selection]| {
+            selection
+                .iter()
+                .enumerate()
+                .filter(|(_, s)| **s == Selection::Replay)
+                .map(|(i, _)| i + 1)
+                .collect::<Vec<_>>()
+        };
+
+        // Packet numbers.
+        let selection = select(PacketSelector::new(3, 5, None, None));
+        assert_eq!(replayed(&selection), [3, 4, 5]);
+        assert_eq!(selection[5], Selection::Stop);
+
+        // Absolute and relative timestamps.
+        let selector = PacketSelector::new(
+            1,
+            u64::MAX,
+            Some(TimeBound::Absolute(400)),
+            Some(TimeBound::Relative(600)),
+        );
+        assert_eq!(replayed(&select(selector)), [4, 5, 6]);
+
+        // Both.
+        let selector = PacketSelector::new(5, 8, Some(TimeBound::Relative(200)), None);
+        assert_eq!(replayed(&select(selector)), [5, 6, 7, 8]);
+    }
+}
@@ -0,0 +1,58 @@
+//! Sink writing the packets to a PCAP/PCAPNG file.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Error, Result},
+};
+
+use crate::{
+    sink::{PacketSink, SinkStats},
+    util::now_ns,
+    writer::PcapWriter,
+};
+
+/// PCAP/PCAPNG file, where the packets are stamped with their send time.
+pub struct FileSink {
+    /// Path of the file.
+    path: String,
+    /// Writer of the file.
+    writer: PcapWriter<BufWriter<File>>,
+    /// Statistics.
+    stats: SinkStats,
+}
+
+impl FileSink {
+    /// Create a file, guessing its format from its path.
+    pub fn create(path: &str) -> Result<FileSink> {
+        Ok(FileSink {
+            path: path.to_string(),
+            writer: PcapWriter::create(path).map_err(|e| {
+                Error::new(
+                    e.kind(),
+                    format!("Failed to create the output file {}: {}", path, e),
+                )
+            })?,
+            stats: SinkStats::default(),
+        })
+    }
+}
+
+impl PacketSink for FileSink {
+    fn name(&self) -> &str {
+        &self.path
+    }
+
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        let len = self.writer.write_packet(data, now_ns())?;
+        self.stats.add(len);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.stats.clone()
+    }
+}
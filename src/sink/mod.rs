@@ -0,0 +1,300 @@
+//! Destinations of the replayed packets.
+
+use std::{
+    io::{Error, ErrorKind, Result},
+    net::{SocketAddr, ToSocketAddrs},
+};
+
+use crate::util;
+
+#[cfg(target_os = "linux")]
+use crate::unix::{
+    raw::RawIpSocket,
+    tap::{TapDevice, TunDevice},
+    xdp::XdpSocket,
+};
+
+mod file;
+mod null;
+mod pcapip;
+mod tunnel;
+
+pub use file::FileSink;
+pub use null::NullSink;
+pub use pcapip::PcapOverIpSink;
+pub use tunnel::TunnelSink;
+
+/// Statistics of a sink.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SinkStats {
+    /// Packets accepted by the sink.
+    pub packets: u64,
+    /// Bytes accepted by the sink.
+    pub bytes: u64,
+    /// Nanoseconds between the first and the last packet accepted, for the sinks measuring
+    /// their throughput.
+    pub active_ns: Option<u64>,
+    /// Counters specific to the sink, as (description, value) pairs.
+    pub extra: Vec<(&'static str, u64)>,
+}
+
+impl SinkStats {
+    /// Account for a packet of which `len` bytes were accepted.
+    pub fn add(&mut self, len: usize) {
+        self.packets += 1;
+        self.bytes += len as u64;
+    }
+}
+
+/// Options of the sinks.
+#[derive(Clone, Debug, Default)]
+pub struct SinkOptions {
+    /// Send the packets through a PACKET_MMAP TX ring (linux network interfaces).
+    pub tx_ring: bool,
+    /// Bypass the queueing discipline of the interfaces (linux network interfaces).
+    pub qdisc_bypass: bool,
+    /// Size of the send buffer of the sockets (linux network interfaces, raw and tunnel
+    /// sockets).
+    pub sndbuf: Option<u32>,
+    /// Priority of the packets (linux network interfaces, raw and tunnel sockets).
+    pub priority: Option<u32>,
+    /// Mark of the packets (linux network interfaces, raw and tunnel sockets).
+    pub mark: Option<u32>,
+    /// Number of packets preloaded in memory, which the AF_XDP sockets keep in their UMEM to
+    /// send them again at each loop without copying them.
+    pub preloaded: Option<usize>,
+}
+
+impl SinkOptions {
+    /// Names of the options which are set, as on the command line.
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if self.tx_ring {
+            names.push("tx-ring");
+        }
+        if self.qdisc_bypass {
+            names.push("qdisc-bypass");
+        }
+        if self.sndbuf.is_some() {
+            names.push("sndbuf");
+        }
+        if self.priority.is_some() {
+            names.push("priority");
+        }
+        if self.mark.is_some() {
+            names.push("mark");
+        }
+        names
+    }
+}
+
+/// Destination of the replayed packets.
+pub trait PacketSink {
+    /// Name of the sink, for the statistics.
+    fn name(&self) -> &str;
+
+    /// Send a packet.
+    /// Return the number of bytes sent, which is lower than the packet length if it was
+    /// truncated. An error of kind `Unsupported` means that the sink cannot send this packet
+    /// (e.g. a non-IP packet to a L3 sink), without preventing it from sending the next ones.
+    fn send(&mut self, data: &[u8]) -> Result<usize>;
+
+    /// Send a batch of packets, in order.
+    /// Return the number of bytes sent of each packet, up to the first packet that could not be
+    /// sent. An error is returned only if no packet could be sent.
+    fn send_batch(&mut self, packets: &[&[u8]]) -> Result<Vec<usize>> {
+        let mut sent = Vec::with_capacity(packets.len());
+        for data in packets {
+            match self.send(data) {
+                Ok(len) => sent.push(len),
+                Err(e) if sent.is_empty() => return Err(e),
+                Err(_) => break,
+            }
+        }
+
+        Ok(sent)
+    }
+
+    /// Prepare for another loop over the PCAPs, of which the sink receives the same packets as
+    /// during the first loop, in the same order.
+    fn rewind(&mut self) {}
+
+    /// Flush the packets buffered by the sink.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Statistics of the sink.
+    fn stats(&self) -> SinkStats;
+
+    /// Names of the options (see `SinkOptions::names`) applied by the sink, the others being
+    /// ignored.
+    fn supported_options(&self) -> &[&'static str] {
+        &[]
+    }
+}
+
+/// Resolve an address with an optional port ("host", "host:port", "[ipv6]", "[ipv6]:port").
+fn resolve(address: &str, default_port: u16) -> Result<SocketAddr> {
+    address
+        .to_socket_addrs()
+        .or_else(|_| (address.trim_matches(['[', ']']), default_port).to_socket_addrs())
+        .map_err(|e| Error::new(e.kind(), format!("Failed to resolve {}: {}", address, e)))?
+        .next()
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("Failed to resolve {}", address),
+            )
+        })
+}
+
+/// Split the specification of a sink of the given kind into its first field and its options:
+/// "FIELD[,OPTION=VALUE]...".
+pub(crate) fn split_spec<'a>(
+    kind: &str,
+    spec: &'a str,
+) -> Result<(&'a str, Vec<(&'a str, &'a str)>)> {
+    let mut fields = spec.split(',');
+    let first = fields.next().unwrap_or_default();
+    let options = fields
+        .map(|option| {
+            option.split_once('=').ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Invalid {} option: {}", kind, option),
+                )
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok((first, options))
+}
+
+/// Open a sink given its specification:
+/// - "file:PATH": PCAP/PCAPNG file (see `FileSink`),
+/// - "null": sink discarding the packets (see `NullSink`),
+/// - "tap:NAME": TAP device, created if it does not exist (linux),
+/// - "tun:NAME": TUN device, created if it does not exist (linux),
+/// - "raw" or "raw:NAME": `IPPROTO_RAW` sockets, optionally bound to an interface (linux),
+/// - "xdp:NAME[,queue=N][,mode=copy|zerocopy]": AF_XDP socket bound to a queue of an interface
+///   (linux, see `XdpSocket`),
+/// - "vxlan:ADDRESS[,vni=N]", "gre:ADDRESS", "erspan:ADDRESS[,session=N]", "tzsp:ADDRESS":
+///   UDP tunnel toward a remote collector (see `TunnelSink`),
+/// - "pcapip:ADDRESS", "pcapip-listen:ADDRESS": PCAP-over-IP stream to a TCP peer, connected to
+///   or accepted (see `PcapOverIpSink`),
+/// - "NAME": network interface.
+///
+/// `budget` is the memory (in bytes) still available for preloading, which is decreased by the
+/// memory the sink uses to keep the preloaded packets (see `SinkOptions::preloaded`).
+pub fn open(spec: &str, options: &SinkOptions, budget: &mut u64) -> Result<Box<dyn PacketSink>> {
+    #[cfg(not(target_os = "linux"))]
+    let _ = budget;
+
+    match spec {
+        "null" => return Ok(Box::new(NullSink::new())),
+        #[cfg(target_os = "linux")]
+        "raw" => return Ok(Box::new(RawIpSocket::new(None, options))),
+        _ => {}
+    }
+
+    match spec.split_once(':') {
+        Some(("file", path)) => Ok(Box::new(FileSink::create(path)?)),
+        Some(("pcapip", address)) => Ok(Box::new(PcapOverIpSink::connect(address)?)),
+        Some(("pcapip-listen", address)) => Ok(Box::new(PcapOverIpSink::listen(address)?)),
+        Some((kind @ ("vxlan" | "gre" | "erspan" | "tzsp"), spec)) => {
+            Ok(Box::new(TunnelSink::open(kind, spec, options)?))
+        }
+        #[cfg(target_os = "linux")]
+        Some(("tap", name)) => Ok(Box::new(TapDevice::open(name)?)),
+        #[cfg(target_os = "linux")]
+        Some(("tun", name)) => Ok(Box::new(TunDevice::open(name)?)),
+        #[cfg(target_os = "linux")]
+        Some(("raw", name)) => Ok(Box::new(RawIpSocket::new(Some(name), options))),
+        #[cfg(target_os = "linux")]
+        Some(("xdp", spec)) => Ok(Box::new(XdpSocket::open(spec, options.preloaded, budget)?)),
+        #[cfg(not(target_os = "linux"))]
+        Some(("tap" | "tun" | "raw" | "xdp", _)) => Err(Error::new(
+            ErrorKind::Unsupported,
+            "TAP/TUN devices, raw and AF_XDP sockets are supported only on linux",
+        )),
+        _ => match util::get_interface(spec) {
+            Some(mut interface) => {
+                interface.set_options(options);
+                Ok(Box::new(interface))
+            }
+            None => Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "Invalid interface {}. Please list the available interfaces with option \
+                     \"--listnics\"",
+                    spec
+                ),
+            )),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Error, ErrorKind, Result};
+
+    use crate::sink::{self, PacketSink, SinkOptions, SinkStats};
+
+    /// Sink accepting a limited number of packets.
+    struct LimitedSink {
+        limit: usize,
+        stats: SinkStats,
+    }
+
+    impl PacketSink for LimitedSink {
+        fn name(&self) -> &str {
+            "limited"
+        }
+
+        fn send(&mut self, data: &[u8]) -> Result<usize> {
+            if self.stats.packets as usize == self.limit {
+                return Err(Error::new(ErrorKind::Other, "Full"));
+            }
+            self.stats.add(data.len());
+            Ok(data.len())
+        }
+
+        fn stats(&self) -> SinkStats {
+            self.stats.clone()
+        }
+    }
+
+    #[test]
+    fn test_send_batch() {
+        let mut sink = LimitedSink {
+            limit: 3,
+            stats: SinkStats::default(),
+        };
+        let packets: [&[u8]; 2] = [&[0; 60], &[0; 100]];
+
+        assert_eq!(sink.send_batch(&packets).unwrap(), [60, 100]);
+        assert_eq!(sink.send_batch(&packets).unwrap(), [60]);
+        assert!(sink.send_batch(&packets).is_err());
+        assert_eq!(sink.stats().packets, 3);
+        assert_eq!(sink.stats().bytes, 220);
+    }
+
+    #[test]
+    fn test_options() {
+        let options = SinkOptions {
+            qdisc_bypass: true,
+            priority: Some(0),
+            mark: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(options.names(), ["qdisc-bypass", "priority", "mark"]);
+        assert!(SinkOptions::default().names().is_empty());
+
+        // The options are ignored by the sinks without sockets.
+        let sink = sink::open("null", &options, &mut 0).unwrap();
+        assert!(sink.supported_options().is_empty());
+        let sink = sink::open("file:/dev/null", &options, &mut 0).unwrap();
+        assert!(sink.supported_options().is_empty());
+    }
+}
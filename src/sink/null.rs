@@ -0,0 +1,39 @@
+//! Sink discarding the packets, to measure the throughput of the replay engine.
+
+use std::{io::Result, time::Instant};
+
+use crate::sink::{PacketSink, SinkStats};
+
+/// Sink only counting the packets.
+#[derive(Default)]
+pub struct NullSink {
+    /// Statistics.
+    stats: SinkStats,
+    /// Time at which the first packet was accepted.
+    first: Option<Instant>,
+}
+
+impl NullSink {
+    /// Construct a null sink.
+    pub fn new() -> NullSink {
+        NullSink::default()
+    }
+}
+
+impl PacketSink for NullSink {
+    fn name(&self) -> &str {
+        "null"
+    }
+
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        let first = self.first.get_or_insert_with(Instant::now);
+        self.stats.active_ns = Some(first.elapsed().as_nanos() as u64);
+
+        self.stats.add(data.len());
+        Ok(data.len())
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.stats.clone()
+    }
+}
@@ -0,0 +1,128 @@
+//! Sink streaming the packets as PCAP-over-IP to a TCP peer.
+
+use std::{
+    io::{BufWriter, Error, Result},
+    net::{SocketAddr, TcpListener, TcpStream},
+};
+
+use crate::{
+    sink::{self, PacketSink, SinkStats},
+    util::now_ns,
+    writer::{OutputFormat, PcapWriter},
+};
+
+/// Default PCAP-over-IP port.
+const PCAP_OVER_IP_PORT: u16 = 57012;
+
+/// Resolve an address with an optional port, or a port alone (listening on all the IPv4
+/// addresses).
+fn resolve(address: &str) -> Result<SocketAddr> {
+    match address.strip_prefix(':') {
+        Some(port) => sink::resolve(&format!("0.0.0.0:{}", port), PCAP_OVER_IP_PORT),
+        None => sink::resolve(address, PCAP_OVER_IP_PORT),
+    }
+}
+
+/// TCP stream fed with a PCAP file (with microsecond timestamps, the most widely supported),
+/// where the packets are stamped with their send time.
+pub struct PcapOverIpSink {
+    /// Name of the sink.
+    name: String,
+    /// Writer of the stream.
+    writer: PcapWriter<BufWriter<TcpStream>>,
+    /// Statistics.
+    stats: SinkStats,
+}
+
+impl PcapOverIpSink {
+    /// Start streaming to a connected peer.
+    fn new(name: String, stream: TcpStream) -> Result<PcapOverIpSink> {
+        // The records are flushed one by one, do not delay them.
+        stream.set_nodelay(true)?;
+
+        Ok(PcapOverIpSink {
+            name,
+            writer: PcapWriter::new(BufWriter::new(stream), OutputFormat::PcapMicro)?,
+            stats: SinkStats::default(),
+        })
+    }
+
+    /// Connect to a PCAP-over-IP listener.
+    pub fn connect(address: &str) -> Result<PcapOverIpSink> {
+        let address = resolve(address)?;
+        let stream = TcpStream::connect(address).map_err(|e| {
+            Error::new(e.kind(), format!("Failed to connect to {}: {}", address, e))
+        })?;
+
+        PcapOverIpSink::new(format!("pcapip:{}", address), stream)
+    }
+
+    /// Listen on an address and wait for a PCAP-over-IP client.
+    pub fn listen(address: &str) -> Result<PcapOverIpSink> {
+        let address = resolve(address)?;
+        let listener = TcpListener::bind(address)
+            .map_err(|e| Error::new(e.kind(), format!("Failed to listen on {}: {}", address, e)))?;
+
+        println!(
+            "Waiting for a PCAP-over-IP client on {}",
+            listener.local_addr()?
+        );
+        let (stream, peer) = listener.accept()?;
+
+        PcapOverIpSink::new(format!("pcapip:{}", peer), stream)
+    }
+}
+
+impl PacketSink for PcapOverIpSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Stream a record, as soon as the packet is sent.
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        let len = self.writer.write_packet(data, now_ns())?;
+        self.writer.flush()?;
+
+        self.stats.add(len);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.stats.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, net::TcpListener};
+
+    use crate::{
+        reader::{PacketSource, PcapReader},
+        sink::{pcapip::PcapOverIpSink, PacketSink},
+    };
+
+    #[test]
+    fn test_pcap_over_ip() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let mut sink = PcapOverIpSink::connect(&address).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        assert_eq!(sink.send(&[1; 60]).unwrap(), 60);
+        assert_eq!(sink.send(&[2; 1514]).unwrap(), 1514);
+        drop(sink);
+
+        let mut output = Vec::new();
+        stream.read_to_end(&mut output).unwrap();
+        let mut reader = PcapReader::new("test", &output[..]).unwrap();
+        assert_eq!(reader.next_packet().unwrap().unwrap().data, [1; 60]);
+        assert_eq!(reader.next_packet().unwrap().unwrap().data, [2; 1514]);
+        assert!(reader.next_packet().unwrap().is_none());
+
+        assert!(PcapOverIpSink::connect("127.0.0.1:1").is_err());
+    }
+}
@@ -0,0 +1,288 @@
+//! Sinks encapsulating the frames in UDP tunnels toward a remote collector.
+
+use std::{
+    io::{Error, ErrorKind, Result},
+    net::{SocketAddr, UdpSocket},
+};
+
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
+
+use crate::sink::{self, PacketSink, SinkOptions, SinkStats};
+
+#[cfg(target_os = "linux")]
+use crate::unix::util::set_socket_options;
+
+/// Default VXLAN port.
+const VXLAN_PORT: u16 = 4789;
+/// Default GRE-in-UDP port (RFC 8086).
+const GRE_UDP_PORT: u16 = 4754;
+/// Default TZSP port.
+const TZSP_PORT: u16 = 37008;
+
+/// GRE protocol type of the Ethernet frames (Transparent Ethernet Bridging).
+const GRE_PROTO_TEB: u16 = 0x6558;
+/// GRE protocol type of ERSPAN type II.
+const GRE_PROTO_ERSPAN: u16 = 0x88be;
+/// GRE flag announcing a sequence number.
+const GRE_FLAG_SEQ: u16 = 0x1000;
+
+/// TZSP version, type ("received tag list"), encapsulated protocol (Ethernet) and end tag.
+const TZSP_HEADER: [u8; 5] = [1, 0, 0, 1, 1];
+
+/// Encapsulation of the frames.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encapsulation {
+    /// VXLAN, with the given network identifier.
+    Vxlan { vni: u32 },
+    /// GRE in UDP.
+    Gre,
+    /// ERSPAN type II in GRE in UDP, with the given session identifier.
+    Erspan { session: u16 },
+    /// TZSP.
+    Tzsp,
+}
+
+impl Encapsulation {
+    /// Parse an encapsulation given its name and its options (e.g. "vni=42").
+    fn parse(kind: &str, options: &[(&str, &str)]) -> Result<Encapsulation> {
+        let invalid = |s: String| Error::new(ErrorKind::InvalidInput, s);
+        let mut encapsulation = match kind {
+            "vxlan" => Encapsulation::Vxlan { vni: 0 },
+            "gre" => Encapsulation::Gre,
+            "erspan" => Encapsulation::Erspan { session: 0 },
+            "tzsp" => Encapsulation::Tzsp,
+            _ => return Err(invalid(format!("Unknown encapsulation {}", kind))),
+        };
+
+        for (name, value) in options {
+            match (&mut encapsulation, *name) {
+                (Encapsulation::Vxlan { vni }, "vni") => {
+                    *vni = value
+                        .parse()
+                        .ok()
+                        .filter(|vni| *vni < 1 << 24)
+                        .ok_or_else(|| invalid(format!("Invalid VNI: {}", value)))?;
+                }
+                (Encapsulation::Erspan { session }, "session") => {
+                    *session = value
+                        .parse()
+                        .ok()
+                        .filter(|session| *session < 1 << 10)
+                        .ok_or_else(|| invalid(format!("Invalid ERSPAN session: {}", value)))?;
+                }
+                _ => return Err(invalid(format!("Unknown {} option: {}", kind, name))),
+            }
+        }
+
+        Ok(encapsulation)
+    }
+
+    /// Default UDP port of the encapsulation.
+    fn default_port(&self) -> u16 {
+        match self {
+            Encapsulation::Vxlan { .. } => VXLAN_PORT,
+            Encapsulation::Gre | Encapsulation::Erspan { .. } => GRE_UDP_PORT,
+            Encapsulation::Tzsp => TZSP_PORT,
+        }
+    }
+
+    /// Write the header of the encapsulation of the `seq`-th frame to `buffer`.
+    fn write_header(&self, buffer: &mut Vec<u8>, seq: u32) {
+        match *self {
+            Encapsulation::Vxlan { vni } => {
+                // Flags (valid VNI) and reserved bits.
+                buffer.extend_from_slice(&[0x08, 0, 0, 0]);
+                buffer.extend_from_slice(&(vni << 8).to_be_bytes());
+            }
+            Encapsulation::Gre => {
+                buffer.extend_from_slice(&0u16.to_be_bytes());
+                buffer.extend_from_slice(&GRE_PROTO_TEB.to_be_bytes());
+            }
+            Encapsulation::Erspan { session } => {
+                buffer.extend_from_slice(&GRE_FLAG_SEQ.to_be_bytes());
+                buffer.extend_from_slice(&GRE_PROTO_ERSPAN.to_be_bytes());
+                buffer.extend_from_slice(&seq.to_be_bytes());
+                // Version 1 (type II) and VLAN 0.
+                buffer.extend_from_slice(&0x1000u16.to_be_bytes());
+                // COS 0, VLAN tags preserved in the frame (En = 3), T 0 and session.
+                buffer.extend_from_slice(&((3 << 11) | session).to_be_bytes());
+                // Reserved and index.
+                buffer.extend_from_slice(&0u32.to_be_bytes());
+            }
+            Encapsulation::Tzsp => buffer.extend_from_slice(&TZSP_HEADER),
+        }
+    }
+}
+
+/// UDP tunnel toward a remote collector.
+pub struct TunnelSink {
+    /// Name of the sink.
+    name: String,
+    /// Encapsulation of the frames.
+    encapsulation: Encapsulation,
+    /// Socket connected to the collector.
+    socket: UdpSocket,
+    /// Number of frames sent (sequence number of ERSPAN).
+    seq: u32,
+    /// Buffer of the encapsulated frame.
+    buffer: Vec<u8>,
+    /// Statistics.
+    stats: SinkStats,
+}
+
+impl TunnelSink {
+    /// Open a tunnel of the given kind ("vxlan", "gre", "erspan" or "tzsp") given its
+    /// specification: "ADDRESS[,OPTION=VALUE]...". The options of the sinks apply to the socket
+    /// on linux.
+    pub fn open(kind: &str, spec: &str, sink_options: &SinkOptions) -> Result<TunnelSink> {
+        let (address, options) = sink::split_spec(kind, spec)?;
+        let encapsulation = Encapsulation::parse(kind, &options)?;
+        let collector = sink::resolve(address, encapsulation.default_port())?;
+        let local: SocketAddr = match collector {
+            SocketAddr::V4(_) => ([0u8; 4], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(collector)?;
+        #[cfg(target_os = "linux")]
+        set_socket_options(socket.as_raw_fd(), sink_options)?;
+        #[cfg(not(target_os = "linux"))]
+        let _ = sink_options;
+
+        Ok(TunnelSink {
+            name: format!("{}:{}", kind, collector),
+            encapsulation,
+            socket,
+            seq: 0,
+            buffer: Vec::new(),
+            stats: SinkStats::default(),
+        })
+    }
+}
+
+impl PacketSink for TunnelSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Send an encapsulated frame to the collector.
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        self.buffer.clear();
+        self.encapsulation.write_header(&mut self.buffer, self.seq);
+        self.buffer.extend_from_slice(data);
+
+        self.socket.send(&self.buffer)?;
+        self.seq = self.seq.wrapping_add(1);
+
+        self.stats.add(data.len());
+        Ok(data.len())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn supported_options(&self) -> &[&'static str] {
+        &["sndbuf", "priority", "mark"]
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.stats.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+
+    use crate::sink::{
+        tunnel::{Encapsulation, TunnelSink},
+        PacketSink, SinkOptions,
+    };
+
+    #[test]
+    fn test_tunnel() {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = collector.local_addr().unwrap().port();
+        let frame = [0xab; 60];
+        let mut buffer = [0; 128];
+
+        let headers: [(&str, &str, &[u8]); 4] = [
+            ("vxlan", ",vni=42", &[0x08, 0, 0, 0, 0, 0, 42, 0]),
+            ("gre", "", &[0, 0, 0x65, 0x58]),
+            (
+                "erspan",
+                ",session=5",
+                &[
+                    0x10, 0, 0x88, 0xbe, 0, 0, 0, 0, 0x10, 0, 0x18, 5, 0, 0, 0, 0,
+                ],
+            ),
+            ("tzsp", "", &[1, 0, 0, 1, 1]),
+        ];
+        for (kind, options, header) in headers {
+            let spec = format!("127.0.0.1:{}{}", port, options);
+            let mut sink = TunnelSink::open(kind, &spec, &SinkOptions::default()).unwrap();
+            assert_eq!(sink.send(&frame).unwrap(), frame.len());
+
+            let len = collector.recv(&mut buffer).unwrap();
+            assert_eq!(&buffer[..header.len()], header);
+            assert_eq!(&buffer[header.len()..len], frame);
+        }
+
+        // Default ports and options.
+        assert_eq!(
+            Encapsulation::parse("vxlan", &[("vni", "16777215")]).unwrap(),
+            Encapsulation::Vxlan { vni: 0xffffff }
+        );
+        assert!(Encapsulation::parse("vxlan", &[("vni", "16777216")]).is_err());
+        assert!(Encapsulation::parse("gre", &[("vni", "1")]).is_err());
+        let sink = TunnelSink::open("tzsp", "[::1]", &SinkOptions::default()).unwrap();
+        assert_eq!(sink.name(), "tzsp:[::1]:37008");
+        assert!(TunnelSink::open("vxlan", "127.0.0.1,vni", &SinkOptions::default()).is_err());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_tunnel_options() {
+        use std::os::unix::io::AsRawFd;
+
+        use libc::{c_int, getsockopt, socklen_t, SOL_SOCKET, SO_MARK, SO_PRIORITY, SO_SNDBUF};
+
+        let get = |sink: &TunnelSink, name: c_int| {
+            let mut value: c_int = 0;
+            let mut len = std::mem::size_of::<c_int>() as socklen_t;
+            let fd = sink.socket.as_raw_fd();
+            let res = unsafe {
+                getsockopt(
+                    fd,
+                    SOL_SOCKET,
+                    name,
+                    (&mut value as *mut c_int).cast(),
+                    &mut len,
+                )
+            };
+            assert_eq!(res, 0);
+            value
+        };
+
+        let options = SinkOptions {
+            sndbuf: Some(65536),
+            priority: Some(3),
+            ..Default::default()
+        };
+        let sink = TunnelSink::open("vxlan", "127.0.0.1", &options).unwrap();
+        assert_eq!(get(&sink, SO_PRIORITY), 3);
+        // The kernel doubles the size of the buffer.
+        assert_eq!(get(&sink, SO_SNDBUF), 2 * 65536);
+        assert_eq!(get(&sink, SO_MARK), 0);
+        assert_eq!(sink.supported_options(), ["sndbuf", "priority", "mark"]);
+
+        // Setting the mark requires CAP_NET_ADMIN.
+        if unsafe { libc::geteuid() } == 0 {
+            let options = SinkOptions {
+                mark: Some(42),
+                ..Default::default()
+            };
+            let sink = TunnelSink::open("gre", "127.0.0.1", &options).unwrap();
+            assert_eq!(get(&sink, SO_MARK), 42);
+        }
+    }
+}
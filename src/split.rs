@@ -0,0 +1,437 @@
+//! Split the packets between the client side (intf1) and the server side (intf2), similarly to
+//! tcpprep.
+
+use std::{
+    collections::HashSet,
+    fs,
+    io::{Error, ErrorKind, Result},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use clap::ValueEnum;
+
+use crate::link::{self, read_u16_be, ETHERTYPE_IPV4, ETHERTYPE_IPV6};
+
+/// TCP protocol number.
+const IPPROTO_TCP: u8 = 6;
+/// UDP protocol number.
+const IPPROTO_UDP: u8 = 17;
+
+/// TCP SYN flag.
+const TCP_SYN: u8 = 0x02;
+/// TCP ACK flag.
+const TCP_ACK: u8 = 0x10;
+
+/// Magic number of the cache files.
+const CACHE_MAGIC: &[u8; 8] = b"PRCACHE\x01";
+
+/// Method used to split the packets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SplitMode {
+    /// Packets sent by the client networks go out on intf1, the others on intf2.
+    Cidr,
+    /// Packets sent to a lower port than their source port go out on intf1, the others on
+    /// intf2.
+    Port,
+    /// Packets of the TCP connection initiators (first SYN seen) go out on intf1, the others
+    /// on intf2. The packets of unknown connections are split by port.
+    Syn,
+}
+
+/// Side of the replay a packet belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    /// Packet sent by a client (intf1).
+    Client,
+    /// Packet sent by a server (intf2).
+    Server,
+}
+
+/// IPv4/IPv6 network.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    /// Network address.
+    addr: IpAddr,
+    /// Length of the network prefix.
+    prefix_len: u32,
+}
+
+impl Cidr {
+    /// Check whether the network contains an address.
+    fn contains(&self, addr: IpAddr) -> bool {
+        // Shifting by the whole width is not allowed.
+        let mask = |bits: u32| (!0u128).checked_shl(bits - self.prefix_len).unwrap_or(0);
+        match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                let mask = mask(32) as u32;
+                u32::from(net) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                let mask = mask(128);
+                u128::from(net) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Parse a network in the "ADDR/LEN" format (a plain address is a network of one host).
+pub fn parse_cidr(s: &str) -> std::result::Result<Cidr, String> {
+    let invalid = || format!("Invalid network: {}", s);
+    let (addr, prefix_len) = s.split_once('/').unwrap_or((s, ""));
+    let addr = addr.parse::<IpAddr>().map_err(|_| invalid())?;
+    let max_prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix_len = match prefix_len {
+        "" => max_prefix_len,
+        prefix_len => prefix_len
+            .parse()
+            .ok()
+            .filter(|len| *len <= max_prefix_len)
+            .ok_or_else(invalid)?,
+    };
+
+    Ok(Cidr { addr, prefix_len })
+}
+
+/// Endpoints of a TCP/UDP packet.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Endpoints {
+    /// Source address.
+    src: IpAddr,
+    /// Destination address.
+    dst: IpAddr,
+    /// Source port.
+    sport: u16,
+    /// Destination port.
+    dport: u16,
+}
+
+impl Endpoints {
+    /// Endpoints of the packets sent in the opposite direction.
+    fn reversed(&self) -> Endpoints {
+        Endpoints {
+            src: self.dst,
+            dst: self.src,
+            sport: self.dport,
+            dport: self.sport,
+        }
+    }
+}
+
+/// Headers of an IP packet relevant to the split.
+struct Headers {
+    /// Source address.
+    src: IpAddr,
+    /// TCP/UDP endpoints, if any.
+    endpoints: Option<Endpoints>,
+    /// TCP flags, for TCP packets.
+    tcp_flags: Option<u8>,
+}
+
+/// Parse the headers of an Ethernet frame.
+/// Only the transport headers directly following the IPv6 header are parsed.
+fn parse_headers(frame: &[u8]) -> Option<Headers> {
+    let (ethertype, offset) = link::parse_ethernet(frame)?;
+    let packet = &frame[offset..];
+    let (src, dst, protocol, header_len, fragmented) = match ethertype {
+        ETHERTYPE_IPV4 => {
+            let src: [u8; 4] = packet.get(12..16)?.try_into().unwrap();
+            let dst: [u8; 4] = packet.get(16..20)?.try_into().unwrap();
+            (
+                IpAddr::V4(Ipv4Addr::from(src)),
+                IpAddr::V4(Ipv4Addr::from(dst)),
+                packet[9],
+                usize::from(packet[0] & 0x0f) * 4,
+                read_u16_be(packet, 6)? & 0x1fff != 0,
+            )
+        }
+        ETHERTYPE_IPV6 => {
+            let src: [u8; 16] = packet.get(8..24)?.try_into().unwrap();
+            let dst: [u8; 16] = packet.get(24..40)?.try_into().unwrap();
+            (
+                IpAddr::V6(Ipv6Addr::from(src)),
+                IpAddr::V6(Ipv6Addr::from(dst)),
+                packet[6],
+                40,
+                false,
+            )
+        }
+        _ => return None,
+    };
+
+    let segment = packet.get(header_len..).filter(|_| !fragmented);
+    let endpoints = match (protocol, segment) {
+        (IPPROTO_TCP | IPPROTO_UDP, Some(segment)) => Some(Endpoints {
+            src,
+            dst,
+            sport: read_u16_be(segment, 0)?,
+            dport: read_u16_be(segment, 2)?,
+        }),
+        _ => None,
+    };
+    let tcp_flags = match (protocol, segment) {
+        (IPPROTO_TCP, Some(segment)) => segment.get(13).copied(),
+        _ => None,
+    };
+
+    Some(Headers {
+        src,
+        endpoints,
+        tcp_flags,
+    })
+}
+
+/// Classifier of the packets.
+struct Classifier {
+    /// Split method.
+    mode: SplitMode,
+    /// Networks of the clients (`SplitMode::Cidr`).
+    client_nets: Vec<Cidr>,
+    /// Endpoints of the packets sent by the TCP connection initiators (`SplitMode::Syn`).
+    clients: HashSet<Endpoints>,
+}
+
+impl Classifier {
+    /// Classify a packet by port: packets sent to a lower port are sent by clients.
+    fn classify_port(endpoints: &Endpoints) -> Side {
+        if endpoints.sport < endpoints.dport {
+            Side::Server
+        } else {
+            Side::Client
+        }
+    }
+
+    /// Classify an Ethernet frame. Frames which are not IP packets are sent by clients.
+    fn classify(&mut self, frame: &[u8]) -> Side {
+        let headers = match parse_headers(frame) {
+            Some(headers) => headers,
+            None => return Side::Client,
+        };
+
+        match self.mode {
+            SplitMode::Cidr => {
+                if self.client_nets.iter().any(|net| net.contains(headers.src)) {
+                    Side::Client
+                } else {
+                    Side::Server
+                }
+            }
+            SplitMode::Port => headers
+                .endpoints
+                .as_ref()
+                .map_or(Side::Client, Classifier::classify_port),
+            SplitMode::Syn => {
+                let endpoints = match headers.endpoints {
+                    Some(endpoints) => endpoints,
+                    None => return Side::Client,
+                };
+
+                if headers
+                    .tcp_flags
+                    .is_some_and(|flags| flags & (TCP_SYN | TCP_ACK) == TCP_SYN)
+                {
+                    self.clients.remove(&endpoints.reversed());
+                    self.clients.insert(endpoints);
+                }
+
+                if self.clients.contains(&endpoints) {
+                    Side::Client
+                } else if self.clients.contains(&endpoints.reversed()) {
+                    Side::Server
+                } else {
+                    Classifier::classify_port(&endpoints)
+                }
+            }
+        }
+    }
+}
+
+/// Splitter of the packets of each loop between the client and the server sides.
+/// The decisions are recorded so that each loop (and each run, through a cache file) sends the
+/// packets out of the same interfaces.
+pub struct Splitter {
+    /// Classifier of the new packets (None if the decisions were loaded from a cache).
+    classifier: Option<Classifier>,
+    /// Decisions for each packet read in a loop (true for the server side).
+    decisions: Vec<bool>,
+    /// Index of the next packet.
+    position: usize,
+}
+
+impl Splitter {
+    /// Construct a splitter classifying the packets with the given method.
+    pub fn new(mode: SplitMode, client_nets: &[Cidr]) -> Splitter {
+        Splitter {
+            classifier: Some(Classifier {
+                mode,
+                client_nets: client_nets.to_vec(),
+                clients: HashSet::new(),
+            }),
+            decisions: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Construct a splitter replaying the decisions stored in a cache file.
+    pub fn load(path: &str) -> Result<Splitter> {
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid cache file {}", path),
+            )
+        };
+        let data = fs::read(path)?;
+
+        let header = data.get(..16).ok_or_else(invalid)?;
+        if &header[..8] != CACHE_MAGIC {
+            return Err(invalid());
+        }
+        let count = u64::from_le_bytes(header[8..].try_into().unwrap()) as usize;
+        let bitmap = &data[16..];
+        if bitmap.len() != count.div_ceil(8) {
+            return Err(invalid());
+        }
+
+        Ok(Splitter {
+            classifier: None,
+            decisions: (0..count)
+                .map(|i| bitmap[i / 8] & (1 << (i % 8)) != 0)
+                .collect(),
+            position: 0,
+        })
+    }
+
+    /// Save the decisions in a cache file.
+    pub fn save(&self, path: &str) -> Result<()> {
+        let mut data = CACHE_MAGIC.to_vec();
+        data.extend_from_slice(&(self.decisions.len() as u64).to_le_bytes());
+        data.resize(data.len() + self.decisions.len().div_ceil(8), 0);
+        for (i, server) in self.decisions.iter().enumerate() {
+            data[16 + i / 8] |= u8::from(*server) << (i % 8);
+        }
+
+        fs::write(path, data)
+    }
+
+    /// Start a new loop.
+    pub fn rewind(&mut self) {
+        self.position = 0;
+    }
+
+    /// Skip a packet which is not sent.
+    pub fn skip(&mut self) {
+        if self.position == self.decisions.len() && self.classifier.is_some() {
+            self.decisions.push(false);
+        }
+        self.position += 1;
+    }
+
+    /// Return the side of a packet which is sent.
+    pub fn split(&mut self, frame: &[u8]) -> Result<Side> {
+        let position = self.position;
+        self.position += 1;
+
+        let server = match (self.decisions.get(position), &mut self.classifier) {
+            (Some(server), _) => *server,
+            (None, Some(classifier)) => {
+                let server = classifier.classify(frame) == Side::Server;
+                self.decisions.push(server);
+                server
+            }
+            (None, None) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "The cache file holds fewer packets than the PCAPs",
+                ))
+            }
+        };
+
+        Ok(if server { Side::Server } else { Side::Client })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::split::{parse_cidr, Side, SplitMode, Splitter};
+
+    /// Ethernet + IPv4 + TCP frame.
+    fn tcp_frame(src: [u8; 4], dst: [u8; 4], sport: u16, dport: u16, flags: u8) -> Vec<u8> {
+        let mut frame = vec![0; 12];
+        frame.extend_from_slice(&[0x08, 0x00, 0x45, 0, 0, 40, 0, 0, 0, 0, 64, 6, 0, 0]);
+        frame.extend_from_slice(&src);
+        frame.extend_from_slice(&dst);
+        frame.extend_from_slice(&sport.to_be_bytes());
+        frame.extend_from_slice(&dport.to_be_bytes());
+        frame.extend_from_slice(&[0; 9]);
+        frame.push(flags);
+        frame.extend_from_slice(&[0; 6]);
+        frame
+    }
+
+    #[test]
+    fn test_parse_cidr() {
+        let net = parse_cidr("10.1.0.0/16").unwrap();
+        assert!(net.contains("10.1.255.1".parse().unwrap()));
+        assert!(!net.contains("10.2.0.1".parse().unwrap()));
+        assert!(!net.contains("::1".parse().unwrap()));
+        assert!(parse_cidr("0.0.0.0/0")
+            .unwrap()
+            .contains("1.2.3.4".parse().unwrap()));
+        assert!(parse_cidr("2001:db8::/32")
+            .unwrap()
+            .contains("2001:db8::1".parse().unwrap()));
+        assert!(parse_cidr("10.0.0.1")
+            .unwrap()
+            .contains("10.0.0.1".parse().unwrap()));
+        assert!(parse_cidr("10.0.0.0/33").is_err());
+        assert!(parse_cidr("10.0.0/8").is_err());
+    }
+
+    #[test]
+    fn test_split() {
+        let client = [10, 0, 0, 1];
+        let server = [10, 1, 0, 1];
+        let request = tcp_frame(client, server, 50000, 80, 0x10);
+        let response = tcp_frame(server, client, 80, 50000, 0x10);
+
+        let mut splitter = Splitter::new(SplitMode::Cidr, &[parse_cidr("10.0.0.0/16").unwrap()]);
+        assert_eq!(splitter.split(&request).unwrap(), Side::Client);
+        assert_eq!(splitter.split(&response).unwrap(), Side::Server);
+        assert_eq!(splitter.split(&[0; 20]).unwrap(), Side::Client);
+
+        let mut splitter = Splitter::new(SplitMode::Port, &[]);
+        assert_eq!(splitter.split(&request).unwrap(), Side::Client);
+        assert_eq!(splitter.split(&response).unwrap(), Side::Server);
+
+        // Connection from a lower port than the one of the server.
+        let syn = tcp_frame(client, server, 1000, 5000, 0x02);
+        let syn_ack = tcp_frame(server, client, 5000, 1000, 0x12);
+        let ack = tcp_frame(client, server, 1000, 5000, 0x10);
+
+        let mut splitter = Splitter::new(SplitMode::Syn, &[]);
+        // Unknown connection, split by port.
+        assert_eq!(splitter.split(&ack).unwrap(), Side::Server);
+        assert_eq!(splitter.split(&syn).unwrap(), Side::Client);
+        assert_eq!(splitter.split(&syn_ack).unwrap(), Side::Server);
+        splitter.skip();
+        assert_eq!(splitter.split(&ack).unwrap(), Side::Client);
+
+        // The decisions are replayed at each loop and through the cache.
+        splitter.rewind();
+        assert_eq!(splitter.split(&syn).unwrap(), Side::Server);
+
+        let path =
+            std::env::temp_dir().join(format!("pcap_replay_split_{}.cache", std::process::id()));
+        let path = path.to_str().unwrap();
+        splitter.save(path).unwrap();
+        let mut loaded = Splitter::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        for side in [Side::Server, Side::Client, Side::Server] {
+            assert_eq!(loaded.split(&ack).unwrap(), side);
+        }
+        loaded.skip();
+        assert_eq!(loaded.split(&ack).unwrap(), Side::Client);
+        assert!(loaded.split(&ack).is_err());
+    }
+}
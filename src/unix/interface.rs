@@ -10,6 +10,14 @@ use std::ops::Drop;
 
 use libc::{c_int, close, freeifaddrs, getifaddrs, ifaddrs, write};
 
+use crate::sink::{PacketSink, SinkOptions, SinkStats};
+
+#[cfg(target_os = "linux")]
+use crate::unix::{
+    ring::TxRing,
+    util::{set_option, set_socket_options},
+};
+
 #[cfg(target_os = "macos")]
 use libc::{ioctl, open, BIOCSETIF, BIOCSHDRCMPLT, O_RDWR};
 
@@ -17,7 +25,10 @@ use libc::{ioctl, open, BIOCSETIF, BIOCSHDRCMPLT, O_RDWR};
 use crate::unix::util::ifreq;
 
 #[cfg(target_os = "linux")]
-use libc::{bind, if_nametoindex, sockaddr, sockaddr_ll, socket, AF_PACKET, ETH_P_ALL, SOCK_RAW};
+use libc::{
+    bind, if_nametoindex, ifreq, ioctl, iovec, mmsghdr, sendmmsg, sockaddr, sockaddr_ll, socket,
+    AF_PACKET, EMSGSIZE, ETH_P_ALL, PACKET_QDISC_BYPASS, SIOCGIFMTU, SOCK_RAW, SOL_PACKET,
+};
 
 /// Network Interface.
 pub struct Interface {
@@ -25,6 +36,14 @@ pub struct Interface {
     pub name: String,
     // Socket fd (when in use).
     fd: Option<c_int>,
+    // Statistics.
+    stats: SinkStats,
+    // Options of the socket (linux).
+    #[cfg(target_os = "linux")]
+    options: SinkOptions,
+    // TX ring (when in use).
+    #[cfg(target_os = "linux")]
+    tx_ring: Option<TxRing>,
 }
 
 impl Interface {
@@ -35,9 +54,27 @@ impl Interface {
             .to_string_lossy()
             .into_owned();
 
-        Interface { name, fd: None }
+        Interface {
+            name,
+            fd: None,
+            stats: SinkStats::default(),
+            #[cfg(target_os = "linux")]
+            options: SinkOptions::default(),
+            #[cfg(target_os = "linux")]
+            tx_ring: None,
+        }
     }
 
+    /// Apply the options of the sinks (only the linux interfaces support them).
+    #[cfg(target_os = "linux")]
+    pub fn set_options(&mut self, options: &SinkOptions) {
+        self.options = options.clone();
+    }
+
+    /// Apply the options of the sinks (only the linux interfaces support them).
+    #[cfg(not(target_os = "linux"))]
+    pub fn set_options(&mut self, _options: &SinkOptions) {}
+
     /// Open a socket (macos).
     #[cfg(target_os = "macos")]
     fn create_socket(&mut self) -> Result<()> {
@@ -80,6 +117,10 @@ impl Interface {
                     ),
                 )),
                 fd => {
+                    if let Err(e) = self.apply_options(fd) {
+                        close(fd);
+                        return Err(e);
+                    }
                     self.fd = Some(fd);
                     Ok(())
                 }
@@ -87,6 +128,21 @@ impl Interface {
         }
     }
 
+    /// Apply the options to a socket (linux).
+    #[cfg(target_os = "linux")]
+    fn apply_options(&self, fd: c_int) -> Result<()> {
+        if self.options.qdisc_bypass {
+            set_option(fd, SOL_PACKET, PACKET_QDISC_BYPASS, &(1 as c_int)).map_err(|e| {
+                Error::new(
+                    ErrorKind::Other,
+                    format!("Failed to set PACKET_QDISC_BYPASS with error: {}.", e),
+                )
+            })?;
+        }
+
+        set_socket_options(fd, &self.options)
+    }
+
     // Bind socket to interface (macos).
     #[cfg(target_os = "macos")]
     fn bind_socket(&mut self) -> Result<()> {
@@ -161,21 +217,100 @@ impl Interface {
             }
         }
     }
+}
+
+impl Interface {
+    /// Length of the largest packet accepted by the interface: its MTU, the Ethernet header
+    /// and a VLAN tag (linux).
+    #[cfg(target_os = "linux")]
+    fn max_len(&self) -> Result<usize> {
+        unsafe {
+            let mut request: ifreq = std::mem::zeroed();
+            std::ptr::copy_nonoverlapping(
+                self.name.as_ptr(),
+                request.ifr_name.as_mut_ptr().cast(),
+                self.name.len().min(request.ifr_name.len() - 1),
+            );
+
+            if ioctl(self.fd.unwrap(), SIOCGIFMTU as _, &mut request) == -1 {
+                return Err(Error::last_os_error());
+            }
+
+            Ok(request.ifr_ifru.ifru_mtu as usize + 14 + 4)
+        }
+    }
+
+    /// Open and bind the socket, and set up its TX ring if required.
+    fn open_socket(&mut self) -> Result<()> {
+        // Create socket.
+        self.create_socket()?;
+
+        // Bind socket.
+        self.bind_socket()?;
+
+        // The TX ring gets its own socket, while the packets not fitting in its frames are
+        // written to the first one. Fall back to write() if the TX ring cannot be set up.
+        #[cfg(target_os = "linux")]
+        if self.options.tx_ring {
+            let fd = self.fd.take();
+            let tx_ring = self
+                .create_socket()
+                .and_then(|_| self.bind_socket())
+                .and_then(|_| self.max_len())
+                .and_then(|max_len| TxRing::new(self.fd.unwrap(), max_len));
+            match tx_ring {
+                Ok(tx_ring) => self.tx_ring = Some(tx_ring),
+                Err(e) => {
+                    if let Some(ring_fd) = self.fd {
+                        unsafe {
+                            close(ring_fd);
+                        }
+                    }
+                    println!(
+                        "Failed to set up the TX ring of {}, falling back to write(): {}",
+                        self.name, e
+                    );
+                }
+            }
+            self.fd = fd;
+        }
+
+        Ok(())
+    }
+}
+
+impl PacketSink for Interface {
+    fn name(&self) -> &str {
+        &self.name
+    }
 
     /// Inject a packet in the interface.
-    pub fn inject_packet(&mut self, data: &[u8]) -> Result<usize> {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
         if self.fd.is_none() {
-            // Create socket.
-            self.create_socket()?;
+            self.open_socket()?;
+        }
 
-            // Bind socket.
-            self.bind_socket()?;
+        // Queue the packet to the TX ring, if it fits in.
+        #[cfg(target_os = "linux")]
+        if let Some(tx_ring) = self.tx_ring.as_mut().filter(|_| TxRing::fits(data)) {
+            tx_ring.queue(data)?;
+            tx_ring.kick(false)?;
+            self.stats.add(data.len());
+            return Ok(data.len());
         }
 
         // Write the data to the socket.
         let res: isize;
         unsafe {
             res = write(self.fd.unwrap(), data.as_ptr().cast(), data.len());
+            #[cfg(target_os = "linux")]
+            if res < 0 && Error::last_os_error().raw_os_error() == Some(EMSGSIZE) {
+                // Packet longer than the MTU.
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("Packet of {} bytes rejected by the interface.", data.len()),
+                ));
+            }
             if res < 0 {
                 return Err(Error::new(
                     ErrorKind::Other,
@@ -185,8 +320,119 @@ impl Interface {
             }
         }
 
+        self.stats.add(res as usize);
         Ok(res as usize)
     }
+
+    /// Inject a batch of packets in the interface with a single system call (linux).
+    #[cfg(target_os = "linux")]
+    fn send_batch(&mut self, packets: &[&[u8]]) -> Result<Vec<usize>> {
+        if self.fd.is_none() {
+            self.open_socket()?;
+        }
+
+        // Queue the packets to the TX ring up to the first one not fitting in, which is written
+        // to the socket.
+        if let Some(tx_ring) = &mut self.tx_ring {
+            let mut sent = Vec::with_capacity(packets.len());
+            for data in packets.iter().take_while(|data| TxRing::fits(data)) {
+                match tx_ring.queue(data) {
+                    Ok(()) => sent.push(data.len()),
+                    Err(e) if sent.is_empty() => return Err(e),
+                    Err(_) => break,
+                }
+            }
+
+            if sent.is_empty() {
+                return self.send(packets[0]).map(|len| vec![len]);
+            }
+
+            tx_ring.kick(false)?;
+            sent.iter().for_each(|len| self.stats.add(*len));
+            return Ok(sent);
+        }
+
+        let mut iovecs: Vec<iovec> = packets
+            .iter()
+            .map(|data| iovec {
+                iov_base: data.as_ptr() as *mut _,
+                iov_len: data.len(),
+            })
+            .collect();
+        let mut messages: Vec<mmsghdr> = iovecs
+            .iter_mut()
+            .map(|iov| unsafe {
+                let mut message: mmsghdr = std::mem::zeroed();
+                message.msg_hdr.msg_iov = iov;
+                message.msg_hdr.msg_iovlen = 1;
+                message
+            })
+            .collect();
+
+        // Only the first packets may be sent.
+        let res = unsafe {
+            sendmmsg(
+                self.fd.unwrap(),
+                messages.as_mut_ptr(),
+                messages.len() as _,
+                0,
+            )
+        };
+        if res < 0 {
+            let e = Error::last_os_error();
+            if e.raw_os_error() == Some(EMSGSIZE) {
+                // The first packet is longer than the MTU.
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!(
+                        "Packet of {} bytes rejected by the interface.",
+                        packets[0].len()
+                    ),
+                ));
+            }
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "Failed to send all the data to the network interface with error: {}.",
+                    e
+                ),
+            ));
+        }
+
+        let sent: Vec<usize> = messages[..res as usize]
+            .iter()
+            .map(|message| message.msg_len as usize)
+            .collect();
+        sent.iter().for_each(|len| self.stats.add(*len));
+        Ok(sent)
+    }
+
+    /// Wait for the packets queued to the TX ring to be sent.
+    fn flush(&mut self) -> Result<()> {
+        #[cfg(target_os = "linux")]
+        if let Some(tx_ring) = &mut self.tx_ring {
+            tx_ring.kick(true)?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn supported_options(&self) -> &[&'static str] {
+        &["tx-ring", "qdisc-bypass", "sndbuf", "priority", "mark"]
+    }
+
+    fn stats(&self) -> SinkStats {
+        #[cfg(target_os = "linux")]
+        if let Some(tx_ring) = &self.tx_ring {
+            return SinkStats {
+                extra: tx_ring.stats(),
+                ..self.stats.clone()
+            };
+        }
+
+        self.stats.clone()
+    }
 }
 
 /// Implement Drop to automatically close the socket.
@@ -1,2 +1,10 @@
 pub mod interface;
-mod util;
+#[cfg(target_os = "linux")]
+pub mod raw;
+#[cfg(target_os = "linux")]
+pub mod ring;
+#[cfg(target_os = "linux")]
+pub mod tap;
+pub mod util;
+#[cfg(target_os = "linux")]
+pub mod xdp;
@@ -0,0 +1,251 @@
+//! Send the IP packets through raw sockets (linux).
+
+use std::ffi::CString;
+use std::io::{Error, ErrorKind, Result};
+
+use libc::{
+    c_int, c_void, close, if_nametoindex, in6_addr, in_addr, sendto, setsockopt, sockaddr,
+    sockaddr_in, sockaddr_in6, socket, socklen_t, AF_INET, AF_INET6, EACCES, EHOSTUNREACH, EINVAL,
+    EMSGSIZE, ENETUNREACH, EPERM, IPPROTO_RAW, SOCK_RAW, SOL_SOCKET, SO_BINDTODEVICE,
+};
+
+use crate::{
+    link::{self, IpVersion},
+    sink::{PacketSink, SinkOptions, SinkStats},
+    unix::util::set_socket_options,
+};
+
+/// `IPPROTO_RAW` sockets, to which the IP packets are sent without their link layer header.
+/// The packets are routed by the kernel according to their destination address, unless the
+/// sockets are bound to an interface.
+pub struct RawIpSocket {
+    // Name of the sink.
+    name: String,
+    // Interface the sockets are bound to, if any.
+    interface: Option<String>,
+    // Index of the interface the sockets are bound to (0 if none).
+    index: u32,
+    // Options of the sockets.
+    options: SinkOptions,
+    // IPv4 socket fd (when in use).
+    fd4: Option<c_int>,
+    // IPv6 socket fd (when in use).
+    fd6: Option<c_int>,
+    // Statistics.
+    stats: SinkStats,
+}
+
+impl RawIpSocket {
+    /// Construct raw sockets, bound to `interface` if provided.
+    pub fn new(interface: Option<&str>, options: &SinkOptions) -> RawIpSocket {
+        RawIpSocket {
+            name: match interface {
+                Some(interface) => format!("raw:{}", interface),
+                None => "raw".to_string(),
+            },
+            interface: interface.map(str::to_string),
+            index: interface
+                .and_then(|interface| CString::new(interface).ok())
+                .map_or(0, |interface| unsafe { if_nametoindex(interface.as_ptr()) }),
+            options: options.clone(),
+            fd4: None,
+            fd6: None,
+            stats: SinkStats::default(),
+        }
+    }
+
+    /// Open a raw socket of the given family, bound to the interface if required, and apply
+    /// the options.
+    fn create_socket(&self, family: c_int) -> Result<c_int> {
+        unsafe {
+            let fd = socket(family, SOCK_RAW, IPPROTO_RAW);
+            if fd == -1 {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!(
+                        "Failed to open raw socket with error: {}.",
+                        Error::last_os_error()
+                    ),
+                ));
+            }
+
+            if let Some(interface) = &self.interface {
+                if setsockopt(
+                    fd,
+                    SOL_SOCKET,
+                    SO_BINDTODEVICE,
+                    interface.as_ptr() as *const c_void,
+                    interface.len() as socklen_t,
+                ) == -1
+                {
+                    let e = Error::last_os_error();
+                    close(fd);
+                    return Err(Error::new(
+                        ErrorKind::Other,
+                        format!(
+                            "Failed to bind raw socket to {} with error: {}.",
+                            interface, e
+                        ),
+                    ));
+                }
+            }
+
+            if let Err(e) = set_socket_options(fd, &self.options) {
+                close(fd);
+                return Err(e);
+            }
+
+            Ok(fd)
+        }
+    }
+
+    /// Send an IP packet to its destination address.
+    fn send_packet(&mut self, version: IpVersion, packet: &[u8]) -> Result<usize> {
+        let invalid = || Error::new(ErrorKind::Unsupported, "Truncated IP header");
+
+        let res = unsafe {
+            match version {
+                IpVersion::V4 => {
+                    let dst: [u8; 4] = packet.get(16..20).ok_or_else(invalid)?.try_into().unwrap();
+                    let fd = match self.fd4 {
+                        Some(fd) => fd,
+                        None => *self.fd4.insert(self.create_socket(AF_INET)?),
+                    };
+
+                    let mut address: sockaddr_in = std::mem::zeroed();
+                    address.sin_family = AF_INET as u16;
+                    address.sin_addr = in_addr {
+                        s_addr: u32::from_ne_bytes(dst),
+                    };
+                    sendto(
+                        fd,
+                        packet.as_ptr().cast(),
+                        packet.len(),
+                        0,
+                        &address as *const sockaddr_in as *const sockaddr,
+                        std::mem::size_of::<sockaddr_in>() as socklen_t,
+                    )
+                }
+                IpVersion::V6 => {
+                    let dst: [u8; 16] = packet.get(24..40).ok_or_else(invalid)?.try_into().unwrap();
+                    let fd = match self.fd6 {
+                        Some(fd) => fd,
+                        None => *self.fd6.insert(self.create_socket(AF_INET6)?),
+                    };
+
+                    let mut address: sockaddr_in6 = std::mem::zeroed();
+                    address.sin6_family = AF_INET6 as u16;
+                    address.sin6_addr = in6_addr { s6_addr: dst };
+                    // Required by the link-local destinations.
+                    address.sin6_scope_id = self.index;
+                    sendto(
+                        fd,
+                        packet.as_ptr().cast(),
+                        packet.len(),
+                        0,
+                        &address as *const sockaddr_in6 as *const sockaddr,
+                        std::mem::size_of::<sockaddr_in6>() as socklen_t,
+                    )
+                }
+            }
+        };
+
+        if res < 0 {
+            let e = Error::last_os_error();
+            // Errors specific to the packet, which do not prevent sending the next ones.
+            let kind = match e.raw_os_error() {
+                Some(EACCES | EHOSTUNREACH | EINVAL | EMSGSIZE | ENETUNREACH | EPERM) => {
+                    ErrorKind::Unsupported
+                }
+                _ => ErrorKind::Other,
+            };
+            return Err(Error::new(
+                kind,
+                format!("Failed to send the IP packet with error: {}.", e),
+            ));
+        }
+
+        Ok(res as usize)
+    }
+}
+
+impl PacketSink for RawIpSocket {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Send the IP packet of a frame through the socket of its version.
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        let (version, packet) = link::strip_ethernet(data)
+            .ok_or_else(|| Error::new(ErrorKind::Unsupported, "Not an IP packet"))?;
+        let res = self.send_packet(version, packet)?;
+
+        // The stripped header counts as sent.
+        let len = data.len() - packet.len() + res;
+        self.stats.add(len);
+        Ok(len)
+    }
+
+    fn supported_options(&self) -> &[&'static str] {
+        &["sndbuf", "priority", "mark"]
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.stats.clone()
+    }
+}
+
+/// Implement Drop to automatically close the sockets.
+impl Drop for RawIpSocket {
+    fn drop(&mut self) {
+        for fd in [self.fd4.take(), self.fd6.take()].into_iter().flatten() {
+            unsafe {
+                close(fd);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libc::{c_int, geteuid, getsockopt, socklen_t, SOL_SOCKET, SO_MARK, SO_PRIORITY};
+
+    use crate::{
+        sink::{PacketSink, SinkOptions},
+        unix::raw::RawIpSocket,
+    };
+
+    #[test]
+    fn test_raw_options() {
+        // Raw sockets require CAP_NET_RAW, and the mark CAP_NET_ADMIN.
+        if unsafe { geteuid() } != 0 {
+            return;
+        }
+
+        let options = SinkOptions {
+            priority: Some(5),
+            mark: Some(42),
+            ..Default::default()
+        };
+        let mut sink = RawIpSocket::new(None, &options);
+
+        // Ethernet frame of an IPv4 UDP packet to the loopback address, to the discard port.
+        let mut frame = vec![0; 14];
+        frame[12..14].copy_from_slice(&[0x08, 0x00]);
+        frame.extend_from_slice(&[0x45, 0, 0, 28, 0, 0, 0, 0, 64, 17, 0, 0, 127, 0, 0, 1]);
+        frame.extend_from_slice(&[127, 0, 0, 1, 0x30, 0x39, 0, 9, 0, 8, 0, 0]);
+        assert_eq!(sink.send(&frame).unwrap(), frame.len());
+
+        let get = |name: c_int| {
+            let mut value: c_int = 0;
+            let mut len = std::mem::size_of::<c_int>() as socklen_t;
+            let value_ptr = (&mut value as *mut c_int).cast();
+            let res =
+                unsafe { getsockopt(sink.fd4.unwrap(), SOL_SOCKET, name, value_ptr, &mut len) };
+            assert_eq!(res, 0);
+            value
+        };
+        assert_eq!(get(SO_PRIORITY), 5);
+        assert_eq!(get(SO_MARK), 42);
+    }
+}
@@ -0,0 +1,354 @@
+//! PACKET_MMAP TX ring of an AF_PACKET socket (linux).
+
+use std::io::{Error, ErrorKind, Result};
+use std::ptr::{self, addr_of, addr_of_mut};
+use std::sync::atomic::{fence, Ordering};
+
+use crate::unix::util::set_option;
+
+use libc::{
+    c_int, c_uint, close, mmap, munmap, poll, pollfd, send, sockaddr_ll, tpacket2_hdr, tpacket_req,
+    tpacket_versions, ENOBUFS, MAP_FAILED, MAP_SHARED, MSG_DONTWAIT, PACKET_TX_RING,
+    PACKET_VERSION, POLLOUT, PROT_READ, PROT_WRITE, SOL_PACKET, TPACKET2_HDRLEN,
+    TP_STATUS_AVAILABLE, TP_STATUS_SEND_REQUEST, TP_STATUS_WRONG_FORMAT,
+};
+
+/// Size of a frame of the ring, holding the header and the packet.
+const FRAME_SIZE: usize = 2048;
+/// Size of a block of the ring.
+const BLOCK_SIZE: usize = 1 << 16;
+/// Number of blocks of the ring.
+const BLOCK_NR: usize = 64;
+/// Offset of the packet in a frame.
+const DATA_OFFSET: usize = TPACKET2_HDRLEN - std::mem::size_of::<sockaddr_ll>();
+/// Length of the Ethernet header, the shortest packet accepted by the interfaces.
+const ETHERNET_HEADER_LEN: usize = 14;
+/// Timeout of the waits for a free frame, in milliseconds.
+const POLL_TIMEOUT_MS: c_int = 100;
+
+/// TX ring shared with the kernel (TPACKET_V2): the packets are copied to the frames of the
+/// ring, then sent at once by the kernel when it is kicked.
+pub struct TxRing {
+    /// Socket fd.
+    fd: c_int,
+    /// Memory mapped ring.
+    map: *mut u8,
+    /// Number of frames of the ring.
+    frame_nr: usize,
+    /// Length of the largest packet accepted by the interface.
+    max_len: usize,
+    /// Index of the next frame to fill.
+    current: usize,
+    /// Number of times no frame was available for a packet.
+    full_cnt: u64,
+    /// Number of times the kernel could not take the packets right away.
+    backpressure_cnt: u64,
+    /// Number of frames rejected by the kernel, then reused.
+    rejected_cnt: u64,
+}
+
+impl TxRing {
+    /// Set up a TX ring for the AF_PACKET socket `fd`, which the ring owns if it succeeds.
+    /// The socket cannot send the packets otherwise anymore. `max_len` is the length of the
+    /// largest packet accepted by the interface, the kernel stopping at the first frame it rejects.
+    pub fn new(fd: c_int, max_len: usize) -> Result<TxRing> {
+        set_option(
+            fd,
+            SOL_PACKET,
+            PACKET_VERSION,
+            &(tpacket_versions::TPACKET_V2 as c_int),
+        )?;
+
+        let request = tpacket_req {
+            tp_block_size: BLOCK_SIZE as c_uint,
+            tp_block_nr: BLOCK_NR as c_uint,
+            tp_frame_size: FRAME_SIZE as c_uint,
+            tp_frame_nr: (BLOCK_SIZE / FRAME_SIZE * BLOCK_NR) as c_uint,
+        };
+        set_option(fd, SOL_PACKET, PACKET_TX_RING, &request)?;
+
+        let map = unsafe {
+            mmap(
+                ptr::null_mut(),
+                BLOCK_SIZE * BLOCK_NR,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED,
+                fd,
+                0,
+            )
+        };
+        if map == MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(TxRing {
+            fd,
+            map: map.cast(),
+            frame_nr: request.tp_frame_nr as usize,
+            max_len,
+            current: 0,
+            full_cnt: 0,
+            backpressure_cnt: 0,
+            rejected_cnt: 0,
+        })
+    }
+
+    /// Header of a frame.
+    fn header(&self, index: usize) -> *mut tpacket2_hdr {
+        // The frames are contiguous, since the blocks hold a whole number of frames.
+        unsafe { self.map.add(index * FRAME_SIZE).cast() }
+    }
+
+    /// Status of a frame.
+    fn status(&self, index: usize) -> u32 {
+        unsafe { ptr::read_volatile(addr_of!((*self.header(index)).tp_status)) }
+    }
+
+    /// Whether a packet fits in a frame.
+    pub fn fits(data: &[u8]) -> bool {
+        data.len() <= FRAME_SIZE - DATA_OFFSET
+    }
+
+    /// Copy a packet to the next frame of the ring, waiting for the kernel to release it if
+    /// required. The packet must fit in a frame, and the packets the interface would reject
+    /// are not queued.
+    pub fn queue(&mut self, data: &[u8]) -> Result<()> {
+        if data.len() < ETHERNET_HEADER_LEN || data.len() > self.max_len {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("Packet of {} bytes rejected by the interface.", data.len()),
+            ));
+        }
+
+        let header = self.header(self.current);
+        let mut full = false;
+
+        loop {
+            let status = self.status(self.current);
+            if status == TP_STATUS_AVAILABLE {
+                break;
+            }
+            if status & TP_STATUS_WRONG_FORMAT != 0 {
+                self.rejected_cnt += 1;
+                break;
+            }
+
+            if !full {
+                full = true;
+                self.full_cnt += 1;
+            }
+
+            if status & TP_STATUS_SEND_REQUEST != 0 {
+                // The ring is full of packets not sent yet.
+                self.kick(true)?;
+            } else {
+                // The packets are being sent.
+                let mut fd = pollfd {
+                    fd: self.fd,
+                    events: POLLOUT,
+                    revents: 0,
+                };
+                if unsafe { poll(&mut fd, 1, POLL_TIMEOUT_MS) } == -1 {
+                    return Err(Error::last_os_error());
+                }
+            }
+        }
+        fence(Ordering::Acquire);
+
+        unsafe {
+            ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                header.cast::<u8>().add(DATA_OFFSET),
+                data.len(),
+            );
+            (*header).tp_len = data.len() as u32;
+            fence(Ordering::Release);
+            ptr::write_volatile(addr_of_mut!((*header).tp_status), TP_STATUS_SEND_REQUEST);
+        }
+
+        self.current = (self.current + 1) % self.frame_nr;
+        Ok(())
+    }
+
+    /// Ask the kernel to send the queued packets, waiting for them to be sent if `wait`.
+    pub fn kick(&mut self, wait: bool) -> Result<()> {
+        let flags = if wait { 0 } else { MSG_DONTWAIT };
+        if unsafe { send(self.fd, ptr::null(), 0, flags) } >= 0 {
+            return Ok(());
+        }
+
+        // The kernel is busy, wait for it to take the packets.
+        let e = Error::last_os_error();
+        if !wait && (e.kind() == ErrorKind::WouldBlock || e.raw_os_error() == Some(ENOBUFS)) {
+            self.backpressure_cnt += 1;
+            return self.kick(true);
+        }
+
+        Err(e)
+    }
+
+    /// Statistics of the ring, as (description, value) pairs.
+    pub fn stats(&self) -> Vec<(&'static str, u64)> {
+        // The frames rejected by the kernel keep their status until they are reused.
+        let rejected = (0..self.frame_nr)
+            .filter(|index| self.status(*index) & TP_STATUS_WRONG_FORMAT != 0)
+            .count() as u64;
+
+        vec![
+            ("TX ring full", self.full_cnt),
+            ("TX ring backpressure", self.backpressure_cnt),
+            ("TX ring rejected", self.rejected_cnt + rejected),
+        ]
+    }
+}
+
+/// Implement Drop to automatically unmap the ring and close the socket.
+impl Drop for TxRing {
+    fn drop(&mut self) {
+        unsafe {
+            munmap(self.map.cast(), BLOCK_SIZE * BLOCK_NR);
+            close(self.fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+    use std::io::ErrorKind;
+    use std::mem::{size_of, zeroed};
+
+    use libc::{
+        bind, c_int, close, geteuid, if_nametoindex, recvfrom, setsockopt, sockaddr, sockaddr_ll,
+        socket, socklen_t, timeval, AF_PACKET, PACKET_OUTGOING, SOCK_RAW, SOL_SOCKET,
+        SO_RCVBUFFORCE, SO_RCVTIMEO,
+    };
+
+    use crate::sink::{self, SinkOptions};
+
+    /// Ethertype of the test packets (local experimental).
+    const ETHERTYPE_TEST: u16 = 0x88b5;
+
+    /// Open a socket receiving the test packets from the loopback interface.
+    fn receiver() -> c_int {
+        unsafe {
+            let fd = socket(AF_PACKET, SOCK_RAW, ETHERTYPE_TEST.to_be() as c_int);
+            assert!(fd >= 0);
+
+            let mut address: sockaddr_ll = zeroed();
+            address.sll_family = AF_PACKET as u16;
+            address.sll_protocol = ETHERTYPE_TEST.to_be();
+            address.sll_ifindex = if_nametoindex(CString::new("lo").unwrap().as_ptr()) as c_int;
+            let res = bind(
+                fd,
+                &address as *const sockaddr_ll as *const sockaddr,
+                size_of::<sockaddr_ll>() as socklen_t,
+            );
+            assert_eq!(res, 0);
+
+            let timeout = timeval {
+                tv_sec: 0,
+                tv_usec: 200_000,
+            };
+            let res = setsockopt(
+                fd,
+                SOL_SOCKET,
+                SO_RCVTIMEO,
+                &timeout as *const timeval as *const _,
+                size_of::<timeval>() as socklen_t,
+            );
+            assert_eq!(res, 0);
+
+            // Hold all the packets, which the loopback interface shows twice.
+            let size: c_int = 16 << 20;
+            let res = setsockopt(
+                fd,
+                SOL_SOCKET,
+                SO_RCVBUFFORCE,
+                &size as *const c_int as *const _,
+                size_of::<c_int>() as socklen_t,
+            );
+            assert_eq!(res, 0);
+
+            fd
+        }
+    }
+
+    /// Receive the sequence numbers of the test packets, until none is received for a while.
+    fn receive(fd: c_int) -> Vec<u16> {
+        let mut received = Vec::new();
+        let mut buffer = [0u8; 4096];
+
+        loop {
+            let mut address: sockaddr_ll = unsafe { zeroed() };
+            let mut address_len = size_of::<sockaddr_ll>() as socklen_t;
+            let len = unsafe {
+                recvfrom(
+                    fd,
+                    buffer.as_mut_ptr().cast(),
+                    buffer.len(),
+                    0,
+                    &mut address as *mut sockaddr_ll as *mut sockaddr,
+                    &mut address_len,
+                )
+            };
+            if len < 0 {
+                return received;
+            }
+            if address.sll_pkttype != PACKET_OUTGOING {
+                received.push(u16::from_be_bytes([buffer[14], buffer[15]]));
+            }
+        }
+    }
+
+    #[test]
+    fn test_tx_ring() {
+        // Sending packets requires CAP_NET_RAW.
+        if unsafe { geteuid() } != 0 {
+            return;
+        }
+
+        let fd = receiver();
+        let options = SinkOptions {
+            tx_ring: true,
+            ..Default::default()
+        };
+        let mut sink = sink::open("lo", &options, &mut 0).unwrap();
+
+        // Packets of various lengths, some not fitting in a frame of the ring, sent one by one
+        // or in batches.
+        let packets: Vec<Vec<u8>> = (0..200u16)
+            .map(|seq| {
+                let mut packet = vec![0; 60 + seq as usize * 17];
+                packet[12..14].copy_from_slice(&ETHERTYPE_TEST.to_be_bytes());
+                packet[14..16].copy_from_slice(&seq.to_be_bytes());
+                packet
+            })
+            .collect();
+        for (index, chunk) in packets.chunks(8).enumerate() {
+            let mut chunk: Vec<&[u8]> = chunk.iter().map(|packet| packet.as_slice()).collect();
+            while !chunk.is_empty() {
+                let sent = match index % 2 {
+                    0 => 1,
+                    _ => sink.send_batch(&chunk).unwrap().len(),
+                };
+                if index % 2 == 0 {
+                    sink.send(chunk[0]).unwrap();
+                }
+                chunk.drain(..sent);
+            }
+        }
+        let res = sink.send(&[0; 10]);
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::Unsupported);
+        sink.flush().unwrap();
+
+        let received = receive(fd);
+        unsafe {
+            close(fd);
+        }
+        assert_eq!(received, (0..200).collect::<Vec<u16>>());
+        let stats = sink.stats();
+        assert_eq!(stats.packets, 200);
+        assert!(stats.extra.contains(&("TX ring rejected", 0)));
+    }
+}
@@ -0,0 +1,316 @@
+//! Inject the packets in TAP/TUN devices (linux).
+
+use std::ffi::CString;
+use std::io::{Error, ErrorKind, Result};
+
+use libc::{
+    c_int, c_short, close, ifreq, ioctl, open, socket, write, AF_INET, IFF_NO_PI, IFF_TAP, IFF_TUN,
+    IFF_UP, IFNAMSIZ, O_RDWR, SIOCGIFFLAGS, SIOCSIFFLAGS, SOCK_DGRAM, TUNSETIFF,
+};
+
+use crate::{
+    link,
+    sink::{PacketSink, SinkStats},
+};
+
+/// Path of the TUN/TAP clone device.
+const TUN_PATH: &str = "/dev/net/tun";
+
+/// TAP device, created if it does not exist yet.
+/// A device created on the fly is removed when the sink is dropped, while an existing
+/// (persistent) device is only attached to.
+pub struct TapDevice {
+    // Name of the device.
+    name: String,
+    // File descriptor of the device.
+    fd: c_int,
+    // Statistics.
+    stats: SinkStats,
+}
+
+/// TUN device, created if it does not exist yet, to which the IP packets are written without
+/// their link layer header.
+pub struct TunDevice {
+    // Name of the device.
+    name: String,
+    // File descriptor of the device.
+    fd: c_int,
+    // Statistics.
+    stats: SinkStats,
+}
+
+/// Construct an `ifreq` for the interface `name`.
+fn ifreq_for(name: &str) -> Result<ifreq> {
+    if name.is_empty() || name.len() >= IFNAMSIZ || name.contains('\0') {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!("Invalid interface name {}.", name),
+        ));
+    }
+
+    unsafe {
+        let mut request: ifreq = std::mem::zeroed();
+        std::ptr::copy_nonoverlapping(
+            name.as_ptr(),
+            request.ifr_name.as_mut_ptr().cast(),
+            name.len(),
+        );
+        Ok(request)
+    }
+}
+
+/// Open a TUN/TAP device with the given flags (`IFF_TAP`/`IFF_TUN`), creating it if required,
+/// and bring it up. Return its file descriptor.
+fn open_tun(name: &str, flags: c_int) -> Result<c_int> {
+    let mut request = ifreq_for(name)?;
+    let path = CString::new(TUN_PATH).unwrap();
+
+    unsafe {
+        let fd = open(path.as_ptr(), O_RDWR);
+        if fd == -1 {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "Failed to open {} with error: {}.",
+                    TUN_PATH,
+                    Error::last_os_error()
+                ),
+            ));
+        }
+
+        request.ifr_ifru.ifru_flags = (flags | IFF_NO_PI) as c_short;
+        if ioctl(fd, TUNSETIFF, &mut request) == -1 {
+            let e = Error::last_os_error();
+            close(fd);
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "Failed to create or attach to the device {} with error: {}.",
+                    name, e
+                ),
+            ));
+        }
+
+        if let Err(e) = set_up(name) {
+            close(fd);
+            return Err(e);
+        }
+
+        Ok(fd)
+    }
+}
+
+/// Bring the interface `name` up, if it is down.
+fn set_up(name: &str) -> Result<()> {
+    let mut request = ifreq_for(name)?;
+
+    unsafe {
+        let fd = socket(AF_INET, SOCK_DGRAM, 0);
+        if fd == -1 {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "Failed to open socket with error: {}.",
+                    Error::last_os_error()
+                ),
+            ));
+        }
+
+        let mut res = ioctl(fd, SIOCGIFFLAGS, &mut request);
+        if res != -1 && request.ifr_ifru.ifru_flags & IFF_UP as c_short == 0 {
+            request.ifr_ifru.ifru_flags |= IFF_UP as c_short;
+            res = ioctl(fd, SIOCSIFFLAGS, &mut request);
+        }
+        let e = Error::last_os_error();
+        close(fd);
+
+        match res {
+            -1 => Err(Error::new(
+                ErrorKind::Other,
+                format!("Failed to bring the device {} up with error: {}.", name, e),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl TapDevice {
+    /// Create the TAP device `name` or attach to it, and bring it up.
+    pub fn open(name: &str) -> Result<TapDevice> {
+        Ok(TapDevice {
+            name: name.to_string(),
+            fd: open_tun(name, IFF_TAP)?,
+            stats: SinkStats::default(),
+        })
+    }
+}
+
+impl PacketSink for TapDevice {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Inject a frame in the device.
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        let res = unsafe { write(self.fd, data.as_ptr().cast(), data.len()) };
+        if res < 0 {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "Failed to write to the device {} with error: {}.",
+                    self.name,
+                    Error::last_os_error()
+                ),
+            ));
+        }
+
+        self.stats.add(res as usize);
+        Ok(res as usize)
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.stats.clone()
+    }
+}
+
+/// Implement Drop to automatically close the device.
+impl Drop for TapDevice {
+    fn drop(&mut self) {
+        unsafe {
+            close(self.fd);
+        }
+    }
+}
+
+impl TunDevice {
+    /// Create the TUN device `name` or attach to it, and bring it up.
+    pub fn open(name: &str) -> Result<TunDevice> {
+        Ok(TunDevice {
+            name: name.to_string(),
+            fd: open_tun(name, IFF_TUN)?,
+            stats: SinkStats::default(),
+        })
+    }
+}
+
+impl PacketSink for TunDevice {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Write the IP packet of a frame to the device (the kernel tells IPv4 from IPv6 by the
+    /// version field).
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        let (_, packet) = link::strip_ethernet(data)
+            .ok_or_else(|| Error::new(ErrorKind::Unsupported, "Not an IP packet"))?;
+
+        let res = unsafe { write(self.fd, packet.as_ptr().cast(), packet.len()) };
+        if res < 0 {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "Failed to write to the device {} with error: {}.",
+                    self.name,
+                    Error::last_os_error()
+                ),
+            ));
+        }
+
+        // The stripped header counts as sent.
+        let len = data.len() - packet.len() + res as usize;
+        self.stats.add(len);
+        Ok(len)
+    }
+
+    fn stats(&self) -> SinkStats {
+        self.stats.clone()
+    }
+}
+
+/// Implement Drop to automatically close the device.
+impl Drop for TunDevice {
+    fn drop(&mut self) {
+        unsafe {
+            close(self.fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::ErrorKind};
+
+    use libc::geteuid;
+
+    use crate::{
+        sink::PacketSink,
+        unix::tap::{TapDevice, TunDevice},
+    };
+
+    /// IPv4 UDP packet from 192.0.2.1 to 192.0.2.2, to the discard port.
+    const IPV4: &[u8] = &[
+        0x45, 0, 0, 29, 0, 0, 0, 0, 64, 17, 0, 0, 192, 0, 2, 1, 192, 0, 2, 2, 0, 9, 0, 9, 0, 9, 0,
+        0, 0xaa,
+    ];
+
+    /// Ethernet frame carrying `payload`, padded to the minimum length.
+    fn frame(ethertype: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0xff; 6];
+        frame.extend_from_slice(&[0x02, 0, 0, 0, 0, 1]);
+        frame.extend_from_slice(&ethertype.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame.resize(frame.len().max(60), 0);
+        frame
+    }
+
+    /// Packets and bytes received by the interface `name`, i.e. written to the device.
+    fn rx_counters(name: &str) -> (u64, u64) {
+        let counter = |counter: &str| {
+            let path = format!("/sys/class/net/{}/statistics/{}", name, counter);
+            fs::read_to_string(path).unwrap().trim().parse().unwrap()
+        };
+        (counter("rx_packets"), counter("rx_bytes"))
+    }
+
+    #[test]
+    fn test_tap_device() {
+        // Creating the device requires CAP_NET_ADMIN.
+        if unsafe { geteuid() } != 0 {
+            return;
+        }
+
+        let mut sink = TapDevice::open("pcaptap0").unwrap();
+        let frames = [frame(0x0800, IPV4), frame(0x88b5, &[1, 2, 3, 4])];
+        for frame in &frames {
+            assert_eq!(sink.send(frame).unwrap(), frame.len());
+        }
+
+        // The frames are injected as they are, whatever they carry.
+        let stats = sink.stats();
+        assert_eq!((stats.packets, stats.bytes), (2, 120));
+        assert_eq!(rx_counters("pcaptap0"), (2, 120));
+        assert!(TapDevice::open("").is_err());
+    }
+
+    #[test]
+    fn test_tun_device() {
+        // Creating the device requires CAP_NET_ADMIN.
+        if unsafe { geteuid() } != 0 {
+            return;
+        }
+
+        let mut sink = TunDevice::open("pcaptun0").unwrap();
+        let ipv4 = frame(0x0800, IPV4);
+        assert_eq!(sink.send(&ipv4).unwrap(), ipv4.len());
+        let tagged = frame(0x8100, &[&[0, 5, 0x08, 0x00][..], IPV4].concat());
+        assert_eq!(sink.send(&tagged).unwrap(), tagged.len());
+        let res = sink.send(&frame(0x0806, &[0; 28]));
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::Unsupported);
+
+        // The device only receives the IP packets, without the Ethernet header, the VLAN tag and
+        // the padding, while the sink accounts for the whole frames.
+        let stats = sink.stats();
+        assert_eq!((stats.packets, stats.bytes), (2, 120));
+        assert_eq!(rx_counters("pcaptun0"), (2, 2 * IPV4.len() as u64));
+    }
+}
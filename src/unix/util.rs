@@ -1,5 +1,17 @@
-/// Utility structs Unix specific.
-/// Redefine some libc structures which are not available on MacOS.
+//! Utility structs and functions Unix specific.
+//! Redefine some libc structures which are not available on MacOS.
+
+#[cfg(target_os = "linux")]
+use std::io::{Error, ErrorKind, Result};
+
+#[cfg(target_os = "linux")]
+use libc::{
+    c_int, c_void, setsockopt, socklen_t, SOL_SOCKET, SO_MARK, SO_PRIORITY, SO_SNDBUF,
+    SO_SNDBUFFORCE,
+};
+
+#[cfg(target_os = "linux")]
+use crate::sink::SinkOptions;
 
 #[cfg(target_os = "macos")]
 use libc::{c_char, c_int, c_short, c_uchar, c_uint, c_void, size_t, sockaddr};
@@ -43,3 +55,52 @@ pub struct ifreq {
     pub ifr_name: [c_char; 16],
     pub ifr_ifru: anonymous_ifr_ifru,
 }
+
+/// Set an option of a socket.
+#[cfg(target_os = "linux")]
+pub fn set_option<T>(fd: c_int, level: c_int, name: c_int, value: &T) -> Result<()> {
+    let res = unsafe {
+        setsockopt(
+            fd,
+            level,
+            name,
+            value as *const T as *const c_void,
+            std::mem::size_of::<T>() as socklen_t,
+        )
+    };
+
+    match res {
+        0 => Ok(()),
+        _ => Err(Error::last_os_error()),
+    }
+}
+
+/// Apply the options of the sinks common to all the sockets: the size of the send buffer, the
+/// priority and the mark of the packets.
+#[cfg(target_os = "linux")]
+pub fn set_socket_options(fd: c_int, options: &SinkOptions) -> Result<()> {
+    let failed = |option: &str, e: Error| {
+        Error::new(
+            ErrorKind::Other,
+            format!("Failed to set {} with error: {}.", option, e),
+        )
+    };
+
+    if let Some(size) = options.sndbuf {
+        // Exceed net.core.wmem_max if allowed to, or stay below it.
+        set_option(fd, SOL_SOCKET, SO_SNDBUFFORCE, &(size as c_int))
+            .or_else(|_| set_option(fd, SOL_SOCKET, SO_SNDBUF, &(size as c_int)))
+            .map_err(|e| failed("SO_SNDBUF", e))?;
+    }
+
+    if let Some(priority) = options.priority {
+        set_option(fd, SOL_SOCKET, SO_PRIORITY, &(priority as c_int))
+            .map_err(|e| failed("SO_PRIORITY", e))?;
+    }
+
+    if let Some(mark) = options.mark {
+        set_option(fd, SOL_SOCKET, SO_MARK, &mark).map_err(|e| failed("SO_MARK", e))?;
+    }
+
+    Ok(())
+}
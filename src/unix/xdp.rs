@@ -0,0 +1,647 @@
+//! Send the frames through AF_XDP sockets (linux).
+
+use std::ffi::CString;
+use std::io::{Error, ErrorKind, Result};
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use libc::{
+    bind, c_int, c_void, close, getsockopt, if_nametoindex, mmap, munmap, off_t, sendto, sockaddr,
+    sockaddr_xdp, socket, socklen_t, xdp_desc, xdp_mmap_offsets, xdp_options, xdp_ring_offset,
+    xdp_statistics, xdp_umem_reg, AF_XDP, EAGAIN, EBUSY, ENOBUFS, MAP_ANONYMOUS, MAP_FAILED,
+    MAP_POPULATE, MAP_PRIVATE, MAP_SHARED, MSG_DONTWAIT, PROT_READ, PROT_WRITE, SOCK_RAW, SOL_XDP,
+    XDP_COPY, XDP_MMAP_OFFSETS, XDP_OPTIONS, XDP_OPTIONS_ZEROCOPY, XDP_PGOFF_TX_RING,
+    XDP_RING_NEED_WAKEUP, XDP_STATISTICS, XDP_TX_RING, XDP_UMEM_COMPLETION_RING,
+    XDP_UMEM_FILL_RING, XDP_UMEM_PGOFF_COMPLETION_RING, XDP_UMEM_REG, XDP_USE_NEED_WAKEUP,
+    XDP_ZEROCOPY,
+};
+
+use crate::{
+    sink::{self, PacketSink, SinkStats},
+    unix::util::set_option,
+};
+
+/// Size of a frame of the UMEM, which is the largest packet that can be sent.
+const FRAME_SIZE: usize = 2048;
+/// Number of frames of the UMEM to which the packets are copied.
+const FRAME_NR: usize = 4096;
+/// Largest number of frames of the UMEM holding preloaded packets (2 GiB).
+const PRELOAD_MAX_FRAME_NR: usize = 1 << 20;
+/// Number of entries of the TX and completion rings.
+const RING_SIZE: u32 = FRAME_NR as u32;
+/// Number of entries of the fill ring (unused, but required by the kernel).
+const FILL_RING_SIZE: u32 = 64;
+/// Duration of the waits for the completions, in microseconds.
+const WAIT_US: u64 = 100;
+/// Number of waits without any completion after which the kernel is considered stuck.
+const WAIT_RETRIES: usize = 10000;
+
+/// Read an option of a socket.
+fn get_option<T>(fd: c_int, level: c_int, name: c_int) -> Result<T> {
+    let mut value = std::mem::MaybeUninit::<T>::zeroed();
+    let mut len = std::mem::size_of::<T>() as socklen_t;
+
+    match unsafe { getsockopt(fd, level, name, value.as_mut_ptr().cast(), &mut len) } {
+        0 => Ok(unsafe { value.assume_init() }),
+        _ => Err(Error::last_os_error()),
+    }
+}
+
+/// Ring shared with the kernel, of which the entries are of type `T`.
+struct Ring<T> {
+    /// Memory mapped ring.
+    map: *mut c_void,
+    /// Length of the mapping.
+    map_len: usize,
+    /// Producer index.
+    producer: *const AtomicU32,
+    /// Consumer index.
+    consumer: *const AtomicU32,
+    /// Flags of the ring.
+    flags: *const AtomicU32,
+    /// Entries of the ring.
+    entries: *mut T,
+    /// Number of entries of the ring (a power of 2).
+    size: u32,
+}
+
+impl<T> Ring<T> {
+    /// Map the ring of `size` entries of the socket `fd`, found at the page offset `pgoff`.
+    fn map(fd: c_int, offsets: &xdp_ring_offset, size: u32, pgoff: off_t) -> Result<Ring<T>> {
+        let map_len = offsets.desc as usize + size as usize * std::mem::size_of::<T>();
+        let map = unsafe {
+            mmap(
+                ptr::null_mut(),
+                map_len,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED | MAP_POPULATE,
+                fd,
+                pgoff,
+            )
+        };
+        if map == MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+
+        let field = |offset: u64| unsafe { map.cast::<u8>().add(offset as usize) };
+        Ok(Ring {
+            map,
+            map_len,
+            producer: field(offsets.producer).cast(),
+            consumer: field(offsets.consumer).cast(),
+            flags: field(offsets.flags).cast(),
+            entries: field(offsets.desc).cast(),
+            size,
+        })
+    }
+
+    fn producer(&self) -> &AtomicU32 {
+        unsafe { &*self.producer }
+    }
+
+    fn consumer(&self) -> &AtomicU32 {
+        unsafe { &*self.consumer }
+    }
+
+    /// Whether the kernel must be woken up to process the ring.
+    fn needs_wakeup(&self) -> bool {
+        unsafe { (*self.flags).load(Ordering::Relaxed) & XDP_RING_NEED_WAKEUP != 0 }
+    }
+
+    /// Entry at the (free running) index `index`.
+    fn entry(&self, index: u32) -> *mut T {
+        unsafe { self.entries.add((index & (self.size - 1)) as usize) }
+    }
+}
+
+/// Implement Drop to automatically unmap the ring.
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        unsafe {
+            munmap(self.map, self.map_len);
+        }
+    }
+}
+
+/// AF_XDP socket bound to a queue of an interface, which sends the frames copied to its UMEM
+/// without going through the network stack. The zero-copy mode is used when the driver
+/// supports it, the copy mode otherwise (e.g. veth interfaces).
+///
+/// When the PCAPs are preloaded, the UMEM starts with a frame per preloaded packet: the packets
+/// are copied to these frames during the first loop, then sent from them at the next loops.
+pub struct XdpSocket {
+    // Name of the sink.
+    name: String,
+    // Socket fd.
+    fd: c_int,
+    // UMEM holding the frames.
+    umem: *mut u8,
+    // Length of the UMEM.
+    umem_len: usize,
+    // Number of frames holding preloaded packets, at the start of the UMEM.
+    preload_frame_nr: usize,
+    // Descriptors of the preloaded packets, in order.
+    preloaded: Vec<xdp_desc>,
+    // Index of the next preloaded packet to send, after the first loop.
+    preload_next: Option<usize>,
+    // Number of frames queued to the TX ring and not completed yet.
+    outstanding: usize,
+    // TX ring.
+    tx: Ring<xdp_desc>,
+    // Completion ring.
+    completion: Ring<u64>,
+    // Addresses of the free frames of the UMEM.
+    free: Vec<u64>,
+    // Number of frames whose transmission was completed.
+    completed_cnt: u64,
+    // Number of times no frame was free for a packet.
+    full_cnt: u64,
+    // Number of packets sent from their preloaded frame.
+    reused_cnt: u64,
+    // Statistics.
+    stats: SinkStats,
+}
+
+impl XdpSocket {
+    /// Open an AF_XDP socket given its specification: "NAME[,queue=N][,mode=copy|zerocopy]".
+    /// The queue 0 is used by default, in zero-copy mode if supported. `preloaded` is the
+    /// number of packets preloaded in memory, if any, which are kept in the UMEM as long as
+    /// `budget` (the memory in bytes still available for preloading) allows it. The budget is
+    /// decreased by the memory used for them.
+    pub fn open(spec: &str, preloaded: Option<usize>, budget: &mut u64) -> Result<XdpSocket> {
+        let invalid = |s: String| Error::new(ErrorKind::InvalidInput, s);
+        let (interface, options) = sink::split_spec("xdp", spec)?;
+
+        let mut queue = 0;
+        let mut flags = 0;
+        for (name, value) in options {
+            match name {
+                "queue" => {
+                    queue = value
+                        .parse()
+                        .map_err(|_| invalid(format!("Invalid XDP queue: {}", value)))?;
+                }
+                "mode" => {
+                    flags = match value {
+                        "copy" => XDP_COPY,
+                        "zerocopy" => XDP_ZEROCOPY,
+                        _ => return Err(invalid(format!("Invalid XDP mode: {}", value))),
+                    };
+                }
+                _ => return Err(invalid(format!("Unknown xdp option: {}", name))),
+            }
+        }
+
+        let index = CString::new(interface)
+            .ok()
+            .map_or(0, |interface| unsafe { if_nametoindex(interface.as_ptr()) });
+        if index == 0 {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "Invalid interface {}. Please list the available interfaces with option \
+                     \"--listnics\"",
+                    interface
+                ),
+            ));
+        }
+
+        unsafe {
+            let fd = socket(AF_XDP, SOCK_RAW, 0);
+            if fd == -1 {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!(
+                        "Failed to open AF_XDP socket with error: {}.",
+                        Error::last_os_error()
+                    ),
+                ));
+            }
+
+            let preload_frame_nr = preloaded
+                .unwrap_or(0)
+                .min(PRELOAD_MAX_FRAME_NR)
+                .min((*budget / FRAME_SIZE as u64) as usize);
+            let umem_len = FRAME_SIZE * (preload_frame_nr + FRAME_NR);
+            let umem = mmap(
+                ptr::null_mut(),
+                umem_len,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS | MAP_POPULATE,
+                -1,
+                0,
+            );
+            if umem == MAP_FAILED {
+                let e = Error::last_os_error();
+                close(fd);
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!("Failed to allocate the UMEM with error: {}.", e),
+                ));
+            }
+
+            match XdpSocket::setup(fd, umem.cast(), umem_len, index, queue, flags) {
+                Ok((tx, completion, zero_copy)) => {
+                    *budget -= (preload_frame_nr * FRAME_SIZE) as u64;
+                    Ok(XdpSocket {
+                        name: format!(
+                            "xdp:{},queue={},mode={}",
+                            interface,
+                            queue,
+                            if zero_copy { "zerocopy" } else { "copy" }
+                        ),
+                        fd,
+                        umem: umem.cast(),
+                        umem_len,
+                        preload_frame_nr,
+                        preloaded: Vec::with_capacity(preload_frame_nr),
+                        preload_next: None,
+                        outstanding: 0,
+                        tx,
+                        completion,
+                        free: (preload_frame_nr..preload_frame_nr + FRAME_NR)
+                            .rev()
+                            .map(|i| (i * FRAME_SIZE) as u64)
+                            .collect(),
+                        completed_cnt: 0,
+                        full_cnt: 0,
+                        reused_cnt: 0,
+                        stats: SinkStats::default(),
+                    })
+                }
+                Err(e) => {
+                    close(fd);
+                    munmap(umem, umem_len);
+                    Err(Error::new(
+                        ErrorKind::Other,
+                        format!(
+                            "Failed to bind AF_XDP socket to {} queue {} with error: {}.",
+                            interface, queue, e
+                        ),
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Register the UMEM, set up the rings and bind the socket to the queue of the interface.
+    /// Return the TX and completion rings, and whether the zero-copy mode is in use.
+    fn setup(
+        fd: c_int,
+        umem: *mut u8,
+        umem_len: usize,
+        index: u32,
+        queue: u32,
+        flags: u16,
+    ) -> Result<(Ring<xdp_desc>, Ring<u64>, bool)> {
+        let registration = xdp_umem_reg {
+            addr: umem as u64,
+            len: umem_len as u64,
+            chunk_size: FRAME_SIZE as u32,
+            headroom: 0,
+            flags: 0,
+            tx_metadata_len: 0,
+        };
+        set_option(fd, SOL_XDP, XDP_UMEM_REG, &registration)?;
+        set_option(fd, SOL_XDP, XDP_UMEM_FILL_RING, &FILL_RING_SIZE)?;
+        set_option(fd, SOL_XDP, XDP_UMEM_COMPLETION_RING, &RING_SIZE)?;
+        set_option(fd, SOL_XDP, XDP_TX_RING, &RING_SIZE)?;
+
+        let offsets: xdp_mmap_offsets = get_option(fd, SOL_XDP, XDP_MMAP_OFFSETS)?;
+        let tx = Ring::map(fd, &offsets.tx, RING_SIZE, XDP_PGOFF_TX_RING)?;
+        let completion = Ring::map(
+            fd,
+            &offsets.cr,
+            RING_SIZE,
+            XDP_UMEM_PGOFF_COMPLETION_RING as off_t,
+        )?;
+
+        // Without any mode, the kernel falls back to the copy mode if the driver does not
+        // support the zero-copy one.
+        let address = sockaddr_xdp {
+            sxdp_family: AF_XDP as u16,
+            sxdp_flags: flags | XDP_USE_NEED_WAKEUP,
+            sxdp_ifindex: index,
+            sxdp_queue_id: queue,
+            sxdp_shared_umem_fd: 0,
+        };
+        let res = unsafe {
+            bind(
+                fd,
+                &address as *const sockaddr_xdp as *const sockaddr,
+                std::mem::size_of::<sockaddr_xdp>() as socklen_t,
+            )
+        };
+        if res == -1 {
+            return Err(Error::last_os_error());
+        }
+
+        let options: xdp_options = get_option(fd, SOL_XDP, XDP_OPTIONS)?;
+        Ok((tx, completion, options.flags & XDP_OPTIONS_ZEROCOPY != 0))
+    }
+
+    /// Take back the frames whose transmission was completed. Return their number.
+    fn reclaim(&mut self) -> usize {
+        let producer = self.completion.producer().load(Ordering::Acquire);
+        let consumer = self.completion.consumer().load(Ordering::Relaxed);
+        let count = producer.wrapping_sub(consumer);
+
+        // The frames of the preloaded packets are kept.
+        let preload_len = (self.preload_frame_nr * FRAME_SIZE) as u64;
+        for i in 0..count {
+            let addr = unsafe { *self.completion.entry(consumer.wrapping_add(i)) };
+            if addr >= preload_len {
+                self.free.push(addr);
+            }
+        }
+        self.completion
+            .consumer()
+            .store(producer, Ordering::Release);
+
+        self.completed_cnt += count as u64;
+        self.outstanding -= count as usize;
+        count as usize
+    }
+
+    /// Ask the kernel to send the queued frames, if it needs to be woken up.
+    fn kick(&self) -> Result<()> {
+        if !self.tx.needs_wakeup() {
+            return Ok(());
+        }
+
+        let res = unsafe { sendto(self.fd, ptr::null(), 0, MSG_DONTWAIT, ptr::null(), 0) };
+        if res >= 0 {
+            return Ok(());
+        }
+
+        // The kernel is busy, or sends the frames in several rounds (copy mode).
+        let e = Error::last_os_error();
+        match e.raw_os_error() {
+            Some(EAGAIN | EBUSY | ENOBUFS) => Ok(()),
+            _ => Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "Failed to send the frames on {} with error: {}.",
+                    self.name, e
+                ),
+            )),
+        }
+    }
+
+    /// Wait until `ready` holds, as the transmission of the frames is completed.
+    fn wait(&mut self, ready: fn(&XdpSocket) -> bool) -> Result<()> {
+        let mut retries = 0;
+
+        while !ready(self) {
+            self.kick()?;
+            if self.reclaim() > 0 {
+                retries = 0;
+                continue;
+            }
+
+            retries += 1;
+            if retries > WAIT_RETRIES {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!(
+                        "Timed out waiting for the transmission of the frames on {}.",
+                        self.name
+                    ),
+                ));
+            }
+            std::thread::sleep(std::time::Duration::from_micros(WAIT_US));
+        }
+
+        Ok(())
+    }
+
+    /// Find the frame holding a packet, copying the packet to a free frame if it was not
+    /// preloaded.
+    fn frame(&mut self, data: &[u8]) -> Result<xdp_desc> {
+        match self.preload_next {
+            // Send the preloaded packet, if it is the expected one.
+            Some(next) => match self.preloaded.get(next) {
+                Some(desc) if self.contents(desc) == data => {
+                    self.preload_next = Some(next + 1);
+                    self.reused_cnt += 1;
+                    return Ok(*desc);
+                }
+                // The packets differ from the first loop, stop sending the preloaded ones.
+                _ => self.preloaded.clear(),
+            },
+            // Preload the packet during the first loop.
+            None if self.preloaded.len() < self.preload_frame_nr => {
+                let desc = xdp_desc {
+                    addr: (self.preloaded.len() * FRAME_SIZE) as u64,
+                    len: data.len() as u32,
+                    options: 0,
+                };
+                self.preloaded.push(desc);
+                self.copy(data, desc.addr);
+                return Ok(desc);
+            }
+            None => {}
+        }
+
+        if self.free.is_empty() {
+            self.reclaim();
+        }
+        if self.free.is_empty() {
+            self.full_cnt += 1;
+            self.wait(|socket| !socket.free.is_empty())?;
+        }
+        let addr = self.free.pop().unwrap();
+        self.copy(data, addr);
+
+        Ok(xdp_desc {
+            addr,
+            len: data.len() as u32,
+            options: 0,
+        })
+    }
+
+    /// Packet held by a frame of the UMEM.
+    fn contents(&self, desc: &xdp_desc) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.umem.add(desc.addr as usize), desc.len as usize) }
+    }
+
+    /// Copy a packet to the frame of the UMEM at `addr`.
+    fn copy(&mut self, data: &[u8], addr: u64) {
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), self.umem.add(addr as usize), data.len());
+        }
+    }
+
+    /// Queue a packet to the TX ring.
+    fn queue(&mut self, data: &[u8]) -> Result<()> {
+        if data.len() > FRAME_SIZE {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("Packet larger than the UMEM frames ({} bytes)", FRAME_SIZE),
+            ));
+        }
+
+        // The completion ring must be able to hold the completions of all the queued frames.
+        if self.outstanding == RING_SIZE as usize && self.reclaim() == 0 {
+            self.full_cnt += 1;
+            self.wait(|socket| socket.outstanding < RING_SIZE as usize)?;
+        }
+
+        let desc = self.frame(data)?;
+        let producer = self.tx.producer().load(Ordering::Relaxed);
+        unsafe {
+            *self.tx.entry(producer) = desc;
+        }
+        self.tx
+            .producer()
+            .store(producer.wrapping_add(1), Ordering::Release);
+        self.outstanding += 1;
+
+        Ok(())
+    }
+}
+
+impl PacketSink for XdpSocket {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Queue a frame and wake up the kernel.
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        self.queue(data)?;
+        self.kick()?;
+        self.reclaim();
+
+        self.stats.add(data.len());
+        Ok(data.len())
+    }
+
+    /// Queue the frames and wake up the kernel once.
+    fn send_batch(&mut self, packets: &[&[u8]]) -> Result<Vec<usize>> {
+        let mut sent = Vec::with_capacity(packets.len());
+        for data in packets {
+            match self.queue(data) {
+                Ok(()) => sent.push(data.len()),
+                Err(e) if sent.is_empty() => return Err(e),
+                Err(_) => break,
+            }
+        }
+        self.kick()?;
+        self.reclaim();
+
+        for len in &sent {
+            self.stats.add(*len);
+        }
+        Ok(sent)
+    }
+
+    /// Send the preloaded packets from the start.
+    fn rewind(&mut self) {
+        self.preload_next = Some(0);
+    }
+
+    /// Wait for the transmission of all the queued frames.
+    fn flush(&mut self) -> Result<()> {
+        self.wait(|socket| socket.outstanding == 0)
+    }
+
+    fn stats(&self) -> SinkStats {
+        let invalid_cnt = get_option::<xdp_statistics>(self.fd, SOL_XDP, XDP_STATISTICS)
+            .map_or(0, |stats| stats.tx_invalid_descs);
+
+        let mut stats = self.stats.clone();
+        stats.extra = vec![
+            ("XDP completions", self.completed_cnt),
+            ("XDP UMEM full", self.full_cnt),
+            ("XDP preloaded packets", self.preloaded.len() as u64),
+            ("XDP preloaded packets sent", self.reused_cnt),
+            ("XDP invalid descriptors", invalid_cnt),
+        ];
+        stats
+    }
+}
+
+/// Implement Drop to automatically close the socket and free the UMEM.
+impl Drop for XdpSocket {
+    fn drop(&mut self) {
+        unsafe {
+            close(self.fd);
+            munmap(self.umem.cast(), self.umem_len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use crate::{
+        sink::PacketSink,
+        unix::xdp::{XdpSocket, FRAME_SIZE},
+    };
+
+    #[test]
+    fn test_xdp_spec() {
+        let kind = |spec| XdpSocket::open(spec, None, &mut 0).err().unwrap().kind();
+
+        assert_eq!(kind("lo,queue=x"), ErrorKind::InvalidInput);
+        assert_eq!(kind("lo,mode=fast"), ErrorKind::InvalidInput);
+        assert_eq!(kind("lo,ring=1"), ErrorKind::InvalidInput);
+        assert_eq!(kind("lo,queue"), ErrorKind::InvalidInput);
+        assert_eq!(kind("nosuchinterface0"), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_xdp_loopback() {
+        // AF_XDP sockets require CAP_NET_RAW and CAP_BPF.
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+
+        let packets: Vec<Vec<u8>> = (0..6000u16)
+            .map(|seq| {
+                let mut packet = vec![0; 60 + seq as usize % 1000];
+                packet[12..14].copy_from_slice(&0x88b5u16.to_be_bytes());
+                packet[14..16].copy_from_slice(&seq.to_be_bytes());
+                packet
+            })
+            .collect();
+        let extra = |sink: &XdpSocket, name: &str| {
+            let stats = sink.stats();
+            stats.extra.iter().find(|(n, _)| *n == name).unwrap().1
+        };
+
+        // The budget holds only 5000 packets, the next ones are copied.
+        let mut budget = 5000 * FRAME_SIZE as u64 + 100;
+        let mut sink = XdpSocket::open("lo", Some(packets.len()), &mut budget).unwrap();
+        assert_eq!(budget, 100);
+        for chunk in packets.chunks(64) {
+            let chunk: Vec<&[u8]> = chunk.iter().map(|packet| packet.as_slice()).collect();
+            assert_eq!(sink.send_batch(&chunk).unwrap().len(), chunk.len());
+        }
+        let res = sink.send(&[0; 4000]);
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::Unsupported);
+        assert_eq!(extra(&sink, "XDP preloaded packets"), 5000);
+
+        // The second loop sends the preloaded packets, up to the first one differing (with the
+        // same length).
+        sink.rewind();
+        for packet in &packets[..100] {
+            sink.send(packet).unwrap();
+        }
+        let mut packet = packets[100].clone();
+        packet[20] = 1;
+        sink.send(&packet).unwrap();
+        sink.send(&packets[101]).unwrap();
+
+        // The next loops copy all the packets.
+        sink.rewind();
+        sink.send(&packets[0]).unwrap();
+        sink.flush().unwrap();
+
+        assert_eq!(sink.stats().packets, 6103);
+        assert_eq!(extra(&sink, "XDP completions"), 6103);
+        assert_eq!(extra(&sink, "XDP preloaded packets sent"), 100);
+        assert_eq!(extra(&sink, "XDP invalid descriptors"), 0);
+    }
+}
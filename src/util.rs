@@ -6,7 +6,45 @@ use crate::windows::interface::{self, Interface};
 #[cfg(not(windows))]
 use crate::unix::interface::{self, Interface};
 
-use std::collections::HashSet;
+use std::{
+    collections::HashSet,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Nanoseconds in a second.
+pub const NS_PER_SEC: u64 = 1_000_000_000;
+
+/// Current time in nanoseconds since the UNIX epoch.
+pub fn now_ns() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}
+
+/// Retrieve the memory (in bytes) available for new allocations, if known.
+#[cfg(target_os = "linux")]
+pub fn available_memory() -> Option<u64> {
+    std::fs::read_to_string("/proc/meminfo")
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))
+        .and_then(|value| {
+            value
+                .trim()
+                .trim_end_matches("kB")
+                .trim()
+                .parse::<u64>()
+                .ok()
+        })
+        .map(|kb| kb * 1024)
+}
+
+/// Retrieve the memory (in bytes) available for new allocations, if known.
+#[cfg(not(target_os = "linux"))]
+pub fn available_memory() -> Option<u64> {
+    None
+}
 
 /// Retrieve a network interface given its name.
 pub fn get_interface(name: &str) -> Option<Interface> {
@@ -18,7 +18,10 @@ use winapi::{
 
 use winsockraw_sys;
 
-use crate::windows::util;
+use crate::{
+    sink::{PacketSink, SinkOptions, SinkStats},
+    windows::util,
+};
 
 /// Network Interface.
 pub struct Interface {
@@ -28,6 +31,8 @@ pub struct Interface {
     pub index: IF_INDEX,
     // Corresponding WinSockRaw socket.
     socket_handle: HANDLE,
+    // Statistics.
+    stats: SinkStats,
 }
 
 impl Interface {
@@ -40,11 +45,21 @@ impl Interface {
             name,
             index: adapter_address.u.s().IfIndex,
             socket_handle: INVALID_HANDLE_VALUE,
+            stats: SinkStats::default(),
         }
     }
 
+    /// Apply the options of the sinks (only the linux interfaces support them).
+    pub fn set_options(&mut self, _options: &SinkOptions) {}
+}
+
+impl PacketSink for Interface {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
     /// Inject a packet in the interface.
-    pub fn inject_packet(&mut self, data: &[u8]) -> Result<usize> {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
         if self.socket_handle == INVALID_HANDLE_VALUE {
             unsafe {
                 // Create socket.
@@ -80,8 +95,13 @@ impl Interface {
             }
         }
 
+        self.stats.add(data.len());
         Ok(data.len())
     }
+
+    fn stats(&self) -> SinkStats {
+        self.stats.clone()
+    }
 }
 
 /// Implement Drop to automatically close the socket.
@@ -0,0 +1,200 @@
+//! Write the replayed packets to PCAP/PCAPNG files.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Result, Write},
+};
+
+use crate::util::NS_PER_SEC;
+
+/// Magic number of the PCAP files with nanosecond timestamps.
+const PCAP_MAGIC_NS: u32 = 0xa1b23c4d;
+/// Magic number of the PCAP files with microsecond timestamps.
+const PCAP_MAGIC_US: u32 = 0xa1b2c3d4;
+/// Snapshot length advertised in the headers.
+const SNAPLEN: u32 = 262144;
+/// Ethernet link type.
+const LINKTYPE_ETHERNET: u16 = 1;
+
+/// PCAPNG block types.
+const SHB_TYPE: u32 = 0x0a0d0d0a;
+const IDB_TYPE: u32 = 0x00000001;
+const EPB_TYPE: u32 = 0x00000006;
+/// PCAPNG byte order magic.
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+/// PCAPNG `if_tsresol` option code.
+const OPT_IF_TSRESOL: u16 = 9;
+
+/// Format of the output files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// PCAP with nanosecond timestamps.
+    Pcap,
+    /// PCAP with microsecond timestamps, for the readers not supporting nanoseconds.
+    PcapMicro,
+    Pcapng,
+}
+
+impl OutputFormat {
+    /// Guess the format from the path of a file: PCAPNG for the ".pcapng" extension, PCAP
+    /// otherwise.
+    pub fn from_path(path: &str) -> OutputFormat {
+        if path.to_lowercase().ends_with(".pcapng") {
+            OutputFormat::Pcapng
+        } else {
+            OutputFormat::Pcap
+        }
+    }
+}
+
+/// Writer of Ethernet frames to a PCAP/PCAPNG file, with nanosecond timestamps.
+pub struct PcapWriter<W: Write> {
+    /// Underlying output.
+    output: W,
+    /// Format of the output.
+    format: OutputFormat,
+}
+
+impl PcapWriter<BufWriter<File>> {
+    /// Create a file, guessing its format from its path.
+    pub fn create(path: &str) -> Result<Self> {
+        PcapWriter::new(
+            BufWriter::new(File::create(path)?),
+            OutputFormat::from_path(path),
+        )
+    }
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Construct a writer, writing the file headers to `output`.
+    pub fn new(mut output: W, format: OutputFormat) -> Result<Self> {
+        match format {
+            OutputFormat::Pcap | OutputFormat::PcapMicro => {
+                let magic = match format {
+                    OutputFormat::PcapMicro => PCAP_MAGIC_US,
+                    _ => PCAP_MAGIC_NS,
+                };
+                output.write_all(&magic.to_le_bytes())?;
+                output.write_all(&2u16.to_le_bytes())?;
+                output.write_all(&4u16.to_le_bytes())?;
+                // Time zone and timestamps accuracy.
+                output.write_all(&[0; 8])?;
+                output.write_all(&SNAPLEN.to_le_bytes())?;
+                output.write_all(&u32::from(LINKTYPE_ETHERNET).to_le_bytes())?;
+            }
+            OutputFormat::Pcapng => {
+                // Section header block, with an unspecified section length.
+                output.write_all(&SHB_TYPE.to_le_bytes())?;
+                output.write_all(&28u32.to_le_bytes())?;
+                output.write_all(&BYTE_ORDER_MAGIC.to_le_bytes())?;
+                output.write_all(&1u16.to_le_bytes())?;
+                output.write_all(&0u16.to_le_bytes())?;
+                output.write_all(&(-1i64).to_le_bytes())?;
+                output.write_all(&28u32.to_le_bytes())?;
+
+                // Interface description block, with nanosecond timestamps.
+                output.write_all(&IDB_TYPE.to_le_bytes())?;
+                output.write_all(&32u32.to_le_bytes())?;
+                output.write_all(&LINKTYPE_ETHERNET.to_le_bytes())?;
+                output.write_all(&0u16.to_le_bytes())?;
+                output.write_all(&SNAPLEN.to_le_bytes())?;
+                output.write_all(&OPT_IF_TSRESOL.to_le_bytes())?;
+                output.write_all(&1u16.to_le_bytes())?;
+                output.write_all(&[9, 0, 0, 0])?;
+                // End of options.
+                output.write_all(&[0; 4])?;
+                output.write_all(&32u32.to_le_bytes())?;
+            }
+        }
+
+        Ok(PcapWriter { output, format })
+    }
+
+    /// Write a frame with the given timestamp (in nanoseconds since the UNIX epoch).
+    pub fn write_packet(&mut self, data: &[u8], ts_ns: u64) -> Result<usize> {
+        let len = data.len() as u32;
+
+        match self.format {
+            OutputFormat::Pcap | OutputFormat::PcapMicro => {
+                let frac = match self.format {
+                    OutputFormat::PcapMicro => ts_ns % NS_PER_SEC / 1000,
+                    _ => ts_ns % NS_PER_SEC,
+                };
+                self.output
+                    .write_all(&((ts_ns / NS_PER_SEC) as u32).to_le_bytes())?;
+                self.output.write_all(&(frac as u32).to_le_bytes())?;
+                self.output.write_all(&len.to_le_bytes())?;
+                self.output.write_all(&len.to_le_bytes())?;
+                self.output.write_all(data)?;
+            }
+            OutputFormat::Pcapng => {
+                let padding = (4 - data.len() % 4) % 4;
+                let block_len = 32 + (data.len() + padding) as u32;
+
+                self.output.write_all(&EPB_TYPE.to_le_bytes())?;
+                self.output.write_all(&block_len.to_le_bytes())?;
+                // Interface ID.
+                self.output.write_all(&0u32.to_le_bytes())?;
+                self.output
+                    .write_all(&((ts_ns >> 32) as u32).to_le_bytes())?;
+                self.output.write_all(&(ts_ns as u32).to_le_bytes())?;
+                self.output.write_all(&len.to_le_bytes())?;
+                self.output.write_all(&len.to_le_bytes())?;
+                self.output.write_all(data)?;
+                self.output.write_all(&[0; 3][..padding])?;
+                self.output.write_all(&block_len.to_le_bytes())?;
+            }
+        }
+
+        Ok(data.len())
+    }
+
+    /// Flush the buffered data to the output.
+    pub fn flush(&mut self) -> Result<()> {
+        self.output.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        reader::{PacketSource, PcapReader},
+        writer::{OutputFormat, PcapWriter},
+    };
+
+    #[test]
+    fn test_write() {
+        let packets: [(&[u8], u64); 3] = [
+            (&[1; 60], 1_669_804_990_123_456_789),
+            (&[2; 61], 1_669_804_990_223_456_789),
+            (&[3; 1514], 1_669_804_991_000_000_001),
+        ];
+
+        for format in [
+            OutputFormat::Pcap,
+            OutputFormat::PcapMicro,
+            OutputFormat::Pcapng,
+        ] {
+            let mut writer = PcapWriter::new(Vec::new(), format).unwrap();
+            for (data, ts_ns) in packets {
+                assert_eq!(writer.write_packet(data, ts_ns).unwrap(), data.len());
+            }
+            let output = writer.output;
+
+            let mut reader = PcapReader::new("test", &output[..]).unwrap();
+            for (data, ts_ns) in packets {
+                let packet = reader.next_packet().unwrap().unwrap();
+                assert_eq!(packet.data, data);
+                assert_eq!(packet.orig_len as usize, data.len());
+                match format {
+                    OutputFormat::PcapMicro => assert_eq!(packet.ts_ns, ts_ns / 1000 * 1000),
+                    _ => assert_eq!(packet.ts_ns, ts_ns),
+                }
+            }
+            assert!(reader.next_packet().unwrap().is_none());
+        }
+
+        assert_eq!(OutputFormat::from_path("a.PCAPNG"), OutputFormat::Pcapng);
+        assert_eq!(OutputFormat::from_path("a.pcap"), OutputFormat::Pcap);
+    }
+}